@@ -57,6 +57,7 @@ fn resolve_param(config: &mut PluginConfig, name: &str, value: u32) {
 		"recovertpb" => config.params.recovertpb = value,
 		"platform" => config.params.platform = value,
 		"edge_bits" => config.params.edge_bits = value,
+		"variant" => config.params.variant = value,
 		n => {
 			warn!(LOGGER, "Configuration param: {} unknown. Ignored.", n);
 		}
@@ -118,6 +119,9 @@ pub fn read_configs(
 						resolve_param(&mut c, k, *params.get(k).unwrap());
 					}
 				}
+				if let Some(duty_cycle) = conf.duty_cycle {
+					c.duty_cycle = duty_cycle.clamp(1, 100);
+				}
 				return_vec.push(c)
 			}
 		}
@@ -35,4 +35,7 @@ mod config;
 mod types;
 
 pub use config::read_configs;
-pub use types::{ConfigError, ConfigMembers, GlobalConfig, GrinMinerPluginConfig, MinerConfig};
+pub use types::{
+	ConfigError, ConfigMembers, GlobalConfig, GrinMinerPluginConfig, MinerConfig,
+	StaleSolutionPolicy, StratumServerConfig,
+};
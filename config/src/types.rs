@@ -28,6 +28,12 @@ pub struct GrinMinerPluginConfig {
 
 	///
 	pub parameters: Option<HashMap<String, u32>>,
+
+	/// percentage of the time the device should spend mining, 1-100
+	pub duty_cycle: Option<u8>,
+
+	/// times of day the device should mine, e.g. "22:00-07:00"
+	pub schedule: Option<String>,
 }
 
 impl Default for GrinMinerPluginConfig {
@@ -35,6 +41,8 @@ impl Default for GrinMinerPluginConfig {
 		GrinMinerPluginConfig {
 			plugin_name: String::new(),
 			parameters: None,
+			duty_cycle: None,
+			schedule: None,
 		}
 	}
 }
@@ -103,6 +111,93 @@ pub struct MinerConfig {
 	/// whether tls is enabled for the stratum server
 	pub stratum_server_tls_enabled: Option<bool>,
 
+	/// name to use for SNI and certificate verification, if it differs
+	/// from the host part of the stratum server address
+	pub stratum_server_tls_server_name: Option<String>,
+
+	/// PEM bundle of additional CA certificates to trust
+	pub stratum_server_tls_ca_file: Option<PathBuf>,
+
+	/// PEM client certificate, for servers requiring client authentication
+	pub stratum_server_tls_client_cert: Option<PathBuf>,
+
+	/// PEM (PKCS#8) private key for the client certificate
+	pub stratum_server_tls_client_key: Option<PathBuf>,
+
+	/// Accept any server certificate and host name. Only meant for
+	/// self-signed test pools, never use this against a real pool
+	pub stratum_server_tls_insecure_skip_verify: Option<bool>,
+
+	/// Additional stratum servers to fail over to, in order of preference
+	pub stratum_failover_servers: Option<Vec<StratumServerConfig>>,
+
+	/// number of failed connection attempts before moving on to the next server
+	pub stratum_failover_attempts: Option<u32>,
+
+	/// seconds without a new job before the current server is considered stalled
+	pub stratum_job_timeout: Option<u64>,
+
+	/// how often (in seconds) to check whether the primary server is back
+	/// while mining on a failover server
+	pub stratum_primary_check_interval: Option<u64>,
+
+	/// seconds to wait for the server to answer a request before giving up on it
+	pub stratum_request_timeout: Option<u64>,
+
+	/// seconds to wait on shutdown for the server to answer our last shares
+	pub stratum_shutdown_timeout: Option<u64>,
+
+	/// how many shares found while disconnected to hold on to for submitting
+	/// once we're reconnected
+	pub stratum_offline_queue_size: Option<usize>,
+
+	/// proxy to tunnel stratum connections through, as a socks5://,
+	/// socks5h:// or http:// url, e.g. socks5h://127.0.0.1:9050
+	pub stratum_proxy: Option<String>,
+
+	/// what to do with solutions found for a job that has since been replaced
+	pub stale_solution_policy: Option<StaleSolutionPolicy>,
+
+	/// if set, each device walks its own slice of the nonce space derived
+	/// from this id and the device's index, instead of picking random nonces
+	pub rig_id: Option<u16>,
+
+	/// how many consecutive nonces to hand a solver at once when walking
+	/// nonces by rig id
+	pub nonce_range: Option<u32>,
+
+	/// seconds to wait before restarting a device that reported an error
+	pub solver_restart_delay: Option<u64>,
+
+	/// longest wait in seconds before restarting an errored device
+	pub solver_restart_max_delay: Option<u64>,
+
+	/// most times a device is restarted within an hour
+	pub solver_max_restarts_per_hour: Option<u32>,
+
+	/// a solve taking this many times the device's last graph time is
+	/// considered hung, 0 to disable the watchdog
+	pub solver_watchdog_multiple: Option<f64>,
+
+	/// shortest time in seconds a solve may take before it's considered hung
+	pub solver_watchdog_min_time: Option<u64>,
+
+	/// seconds to wait for solvers to stop when shutting down
+	pub solver_shutdown_timeout: Option<u64>,
+
+	/// seconds to wait before the first attempt to reconnect to a server
+	pub stratum_reconnect_initial_delay: Option<u64>,
+
+	/// longest wait between reconnect attempts, in seconds
+	pub stratum_reconnect_max_delay: Option<u64>,
+
+	/// factor the wait between reconnect attempts grows by after each failure
+	pub stratum_reconnect_multiplier: Option<f64>,
+
+	/// fraction of the wait to randomly add or take off each reconnect
+	/// attempt, between 0 and 1
+	pub stratum_reconnect_jitter: Option<f64>,
+
 	/// plugin dir
 	pub miner_plugin_dir: Option<PathBuf>,
 
@@ -120,10 +215,102 @@ impl Default for MinerConfig {
 			stratum_server_login: None,
 			stratum_server_password: None,
 			stratum_server_tls_enabled: None,
+			stratum_server_tls_server_name: None,
+			stratum_server_tls_ca_file: None,
+			stratum_server_tls_client_cert: None,
+			stratum_server_tls_client_key: None,
+			stratum_server_tls_insecure_skip_verify: None,
+			stratum_failover_servers: None,
+			stratum_failover_attempts: None,
+			stratum_job_timeout: None,
+			stratum_primary_check_interval: None,
+			stratum_request_timeout: None,
+			stratum_shutdown_timeout: None,
+			stratum_offline_queue_size: None,
+			stratum_proxy: None,
+			stale_solution_policy: None,
+			rig_id: None,
+			nonce_range: None,
+			solver_restart_delay: None,
+			solver_restart_max_delay: None,
+			solver_max_restarts_per_hour: None,
+			solver_watchdog_multiple: None,
+			solver_watchdog_min_time: None,
+			solver_shutdown_timeout: None,
+			stratum_reconnect_initial_delay: None,
+			stratum_reconnect_max_delay: None,
+			stratum_reconnect_multiplier: None,
+			stratum_reconnect_jitter: None,
+		}
+	}
+}
+
+impl MinerConfig {
+	/// All configured stratum servers, primary first, followed by the
+	/// failover servers in the order they were given
+	pub fn stratum_servers(&self) -> Vec<StratumServerConfig> {
+		let mut servers = vec![StratumServerConfig {
+			addr: self.stratum_server_addr.clone(),
+			login: self.stratum_server_login.clone(),
+			password: self.stratum_server_password.clone(),
+			tls_enabled: self.stratum_server_tls_enabled,
+			tls_server_name: self.stratum_server_tls_server_name.clone(),
+			tls_ca_file: self.stratum_server_tls_ca_file.clone(),
+			tls_client_cert: self.stratum_server_tls_client_cert.clone(),
+			tls_client_key: self.stratum_server_tls_client_key.clone(),
+			tls_insecure_skip_verify: self.stratum_server_tls_insecure_skip_verify,
+		}];
+		if let Some(s) = self.stratum_failover_servers.as_ref() {
+			servers.extend(s.iter().cloned());
 		}
+		servers
 	}
 }
 
+/// What to do with solutions found for a job that has since been replaced
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StaleSolutionPolicy {
+	/// Drop any solution that isn't for the current job
+	Drop,
+	/// Submit solutions for earlier jobs at the current height, which the
+	/// server will usually still accept, and drop those for older heights
+	SubmitSameHeight,
+	/// Submit everything and leave it to the server to reject stale ones
+	SubmitAll,
+}
+
+/// Connection details for a single stratum server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StratumServerConfig {
+	/// stratum server address
+	pub addr: String,
+
+	/// login for the stratum server
+	pub login: Option<String>,
+
+	/// password for the stratum server
+	pub password: Option<String>,
+
+	/// whether tls is enabled for the stratum server
+	pub tls_enabled: Option<bool>,
+
+	/// name to use for SNI and certificate verification
+	pub tls_server_name: Option<String>,
+
+	/// PEM bundle of additional CA certificates to trust
+	pub tls_ca_file: Option<PathBuf>,
+
+	/// PEM client certificate
+	pub tls_client_cert: Option<PathBuf>,
+
+	/// PEM (PKCS#8) private key for the client certificate
+	pub tls_client_key: Option<PathBuf>,
+
+	/// Accept any server certificate and host name (test pools only)
+	pub tls_insecure_skip_verify: Option<bool>,
+}
+
 /// separately for now, then put them together as a single
 /// ServerConfig object afterwards. This is to flatten
 /// out the configuration file into logical sections,
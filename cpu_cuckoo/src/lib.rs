@@ -0,0 +1,390 @@
+extern crate blake2_rfc;
+extern crate byteorder;
+extern crate grin_miner_plugin as plugin;
+extern crate hashbrown;
+extern crate libc;
+
+use blake2_rfc::blake2b::blake2b;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use libc::*;
+use plugin::*;
+use std::io::Cursor;
+use std::io::Error;
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+pub use self::finder::Graph;
+pub use self::trimmer::{Edge, Trimmer};
+
+mod finder;
+mod siphash;
+mod trimmer;
+
+/// Smallest and largest graphs the CPU solver will take on
+const MIN_EDGE_BITS: u8 = 19;
+const MAX_EDGE_BITS: u8 = 29;
+const DEFAULT_EDGE_BITS: u8 = 29;
+
+/// How the edges of the graph are generated from the header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+	/// Cuckatoo, one siphash per endpoint
+	Cuckatoo,
+	/// Cuckaroo, both endpoints from one hash in blocks of 64 siphashes
+	Cuckaroo,
+}
+
+impl Variant {
+	/// Variant selected by the `variant` solver param
+	pub fn from_param(variant: u32) -> Variant {
+		match variant {
+			1 => Variant::Cuckaroo,
+			_ => Variant::Cuckatoo,
+		}
+	}
+}
+
+struct Solver {
+	trimmer: Mutex<Trimmer>,
+	stop: AtomicBool,
+	mutate_nonce: bool,
+}
+
+/// Creates a solver context for the given params
+///
+/// # Safety
+/// `params` must point to valid solver params
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn create_solver_ctx(params: *mut SolverParams) -> *mut SolverCtx {
+	unsafe {
+		let mut edge_bits = (*params).edge_bits as u8;
+		if !(MIN_EDGE_BITS..=MAX_EDGE_BITS).contains(&edge_bits) {
+			edge_bits = DEFAULT_EDGE_BITS;
+		}
+		let trimmer = Trimmer::new(
+			Variant::from_param((*params).variant),
+			edge_bits,
+			(*params).nthreads as usize,
+		);
+		let solver = Solver {
+			trimmer: Mutex::new(trimmer),
+			stop: AtomicBool::new(false),
+			mutate_nonce: (*params).mutate_nonce,
+		};
+		let solver_box = Box::new(solver);
+		let solver_ref = Box::leak(solver_box);
+		solver_ref as *mut Solver as *mut SolverCtx
+	}
+}
+
+/// Frees a solver context
+///
+/// # Safety
+/// `solver_ctx_ptr` must come from `create_solver_ctx` and not be used again
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn destroy_solver_ctx(solver_ctx_ptr: *mut SolverCtx) {
+	unsafe {
+		// create box to clear memory
+		let solver_ptr = solver_ctx_ptr as *mut Solver;
+		let _solver_box = Box::from_raw(solver_ptr);
+	}
+}
+
+/// Asks a running solve to return early
+///
+/// # Safety
+/// `solver_ctx_ptr` must be a live context from `create_solver_ctx`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stop_solver(solver_ctx_ptr: *mut SolverCtx) {
+	unsafe {
+		let solver_ptr = solver_ctx_ptr as *mut Solver;
+		(*solver_ptr).stop.store(true, Ordering::Relaxed);
+	}
+}
+
+/// Fills in the default solver params
+///
+/// # Safety
+/// `params` must point to valid solver params
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fill_default_params(params: *mut SolverParams) {
+	unsafe {
+		(*params).nthreads = 1;
+		(*params).device = 0;
+		(*params).edge_bits = DEFAULT_EDGE_BITS as u32;
+		(*params).variant = 0;
+	}
+}
+
+/// Solves the graph for one header and nonce. Only ever covers the one
+/// nonce, however large a range it's asked to walk, and reports its
+/// solutions at that nonce
+///
+/// # Safety
+/// `ctx` must be a live context from `create_solver_ctx`, `header_ptr` must
+/// point to `header_length` bytes and `solutions` and `stats` must be valid
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn run_solver(
+	ctx: *mut SolverCtx,
+	header_ptr: *const c_uchar,
+	header_length: u32,
+	nonce: u64,
+	_range: u32,
+	solutions: *mut SolverSolutions,
+	stats: *mut SolverStats,
+) -> u32 {
+	unsafe {
+		let start = SystemTime::now();
+		let solver_ptr = ctx as *mut Solver;
+		let solver = &*solver_ptr;
+		solver.stop.store(false, Ordering::Relaxed);
+		let mut header = Vec::with_capacity(header_length as usize);
+		let r_ptr = header.as_mut_ptr();
+		ptr::copy_nonoverlapping(header_ptr, r_ptr, header_length as usize);
+		header.set_len(header_length as usize);
+		let k = match set_header_nonce(&header, Some(nonce as u32), solver.mutate_nonce) {
+			Err(_e) => {
+				return 2;
+			}
+			Ok(v) => v,
+		};
+		let mut trimmer = solver.trimmer.lock().unwrap();
+		let sols = match trimmer
+			.run(&k, &solver.stop)
+			.map(|e| Graph::search(&e, trimmer.variant))
+		{
+			Some(Ok(sols)) => sols,
+			Some(Err(e)) => {
+				// let the miner restart us rather than take it down
+				let reason = format!("Cycle search failed: {}", e);
+				let n = std::cmp::min((*stats).error_reason.len(), reason.len());
+				#[allow(dangerous_implicit_autorefs)]
+				(*stats).error_reason[..n].copy_from_slice(&reason.as_bytes()[..n]);
+				(*stats).has_errored = true;
+				return 1;
+			}
+			None => vec![],
+		};
+		let end = SystemTime::now();
+		let elapsed = end.duration_since(start).unwrap();
+		let edge_bits = trimmer.edge_bits as u32;
+		(*solutions).edge_bits = edge_bits;
+		(*solutions).num_sols = sols.len().min(MAX_SOLS) as u32;
+		for (i, sol) in sols.into_iter().take(MAX_SOLS).enumerate() {
+			(*solutions).sols[i].nonce = nonce;
+			(*solutions).sols[i].proof.copy_from_slice(&sol.nonces);
+		}
+		(*stats).edge_bits = edge_bits;
+		(*stats).device_id = 0;
+		let name = format!("CPU {:?}", trimmer.variant);
+		let n = std::cmp::min((*stats).device_name.len(), name.len());
+		#[allow(dangerous_implicit_autorefs)]
+		(*stats).device_name[..n].copy_from_slice(&name.as_bytes()[..n]);
+		(*stats).last_solution_time = duration_to_u64(elapsed);
+		(*stats).last_start_time =
+			duration_to_u64(start.duration_since(SystemTime::UNIX_EPOCH).unwrap());
+		(*stats).last_end_time =
+			duration_to_u64(end.duration_since(SystemTime::UNIX_EPOCH).unwrap());
+		0
+	}
+}
+
+fn duration_to_u64(elapsed: Duration) -> u64 {
+	elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64
+}
+
+pub fn set_header_nonce(
+	header: &[u8],
+	nonce: Option<u32>,
+	mutate_nonce: bool,
+) -> Result<[u64; 4], Error> {
+	if let Some(n) = nonce {
+		let len = header.len();
+		let mut header = header.to_owned();
+		if mutate_nonce {
+			header.truncate(len - 4);
+			header.write_u32::<LittleEndian>(n)?;
+		}
+		create_siphash_keys(&header)
+	} else {
+		create_siphash_keys(header)
+	}
+}
+
+pub fn create_siphash_keys(header: &[u8]) -> Result<[u64; 4], Error> {
+	let h = blake2b(32, &[], header);
+	let hb = h.as_bytes();
+	let mut rdr = Cursor::new(hb);
+	Ok([
+		rdr.read_u64::<LittleEndian>()?,
+		rdr.read_u64::<LittleEndian>()?,
+		rdr.read_u64::<LittleEndian>()?,
+		rdr.read_u64::<LittleEndian>()?,
+	])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::siphash::{EDGE_BLOCK_MASK, siphash_block, siphash24};
+
+	const EDGE_BITS: u32 = 19;
+
+	/// Checks a proof the way grin's Cuckatoo and Cuckaroo verifiers do
+	fn verify(k: &[u64; 4], variant: Variant, nonces: &[u64]) -> bool {
+		let mask = (1u64 << EDGE_BITS) - 1;
+		let mut uvs = vec![0u64; 2 * PROOFSIZE];
+		let (mut xor0, mut xor1) = match variant {
+			Variant::Cuckatoo => ((PROOFSIZE as u64 / 2) & 1, (PROOFSIZE as u64 / 2) & 1),
+			Variant::Cuckaroo => (0, 0),
+		};
+		for n in 0..PROOFSIZE {
+			if nonces[n] > mask || (n > 0 && nonces[n] <= nonces[n - 1]) {
+				return false;
+			}
+			let (u, v) = match variant {
+				Variant::Cuckatoo => (
+					siphash24(k, 2 * nonces[n]) & mask,
+					siphash24(k, 2 * nonces[n] + 1) & mask,
+				),
+				Variant::Cuckaroo => {
+					let e = siphash_block(k, nonces[n] & !EDGE_BLOCK_MASK)
+						[(nonces[n] & EDGE_BLOCK_MASK) as usize];
+					(e & mask, (e >> 32) & mask)
+				}
+			};
+			uvs[2 * n] = u;
+			uvs[2 * n + 1] = v;
+			xor0 ^= u;
+			xor1 ^= v;
+		}
+		if xor0 | xor1 != 0 {
+			return false;
+		}
+		let joins = |a: u64, b: u64| match variant {
+			Variant::Cuckatoo => a >> 1 == b >> 1,
+			Variant::Cuckaroo => a == b,
+		};
+		let (mut n, mut i) = (0, 0);
+		loop {
+			let mut j = i;
+			let mut k = i;
+			loop {
+				k = (k + 2) % (2 * PROOFSIZE);
+				if k == i {
+					break;
+				}
+				if joins(uvs[k], uvs[i]) {
+					if j != i {
+						return false;
+					}
+					j = k;
+				}
+			}
+			if j == i || (variant == Variant::Cuckatoo && uvs[j] == uvs[i]) {
+				return false;
+			}
+			i = j ^ 1;
+			n += 1;
+			if i == 0 {
+				break;
+			}
+		}
+		n == PROOFSIZE
+	}
+
+	fn solve(variant: u32, nonce: u64) -> (SolverSolutions, [u64; 4]) {
+		let header = [0u8; 80];
+		let mut params = SolverParams::default();
+		let mut solutions = SolverSolutions::default();
+		let mut stats = SolverStats::default();
+		unsafe {
+			fill_default_params(&mut params);
+			params.edge_bits = EDGE_BITS;
+			params.variant = variant;
+			params.mutate_nonce = true;
+			let ctx = create_solver_ctx(&mut params);
+			let res = run_solver(
+				ctx,
+				header.as_ptr(),
+				header.len() as u32,
+				nonce,
+				1,
+				&mut solutions,
+				&mut stats,
+			);
+			destroy_solver_ctx(ctx);
+			assert_eq!(res, 0);
+		}
+		assert_eq!(stats.edge_bits, EDGE_BITS);
+		let k = set_header_nonce(&header, Some(nonce as u32), true).unwrap();
+		(solutions, k)
+	}
+
+	fn check(variant: u32, nonce: u64) {
+		let (sols, k) = solve(variant, nonce);
+		assert!(sols.num_sols > 0);
+		for sol in &sols.sols[..sols.num_sols as usize] {
+			assert_eq!(sol.nonce, nonce);
+			assert!(verify(&k, Variant::from_param(variant), &sol.proof));
+		}
+	}
+
+	#[test]
+	fn solve_cuckatoo() {
+		check(0, 68);
+	}
+
+	#[test]
+	fn solve_cuckaroo() {
+		check(1, 71);
+	}
+
+	#[test]
+	fn search_reports_at_most_max_sols() {
+		// Several 41 edge paths between the two ends of one last edge, so
+		// adding that edge closes a 42-cycle through each of them at once
+		let paths = MAX_SOLS as u32 + 2;
+		let mut edges = vec![];
+		for p in 0..paths {
+			let off = 100 * (p + 1);
+			edges.push(Edge {
+				u: off,
+				v: 1,
+				nonce: off + 1,
+			});
+			for i in 2..PROOFSIZE as u32 - 1 {
+				edges.push(Edge {
+					u: off + i - 1,
+					v: off + i,
+					nonce: off + i,
+				});
+			}
+			edges.push(Edge {
+				u: PROOFSIZE as u32 - 2,
+				v: off + PROOFSIZE as u32 - 1,
+				nonce: off + PROOFSIZE as u32 - 1,
+			});
+		}
+		edges.push(Edge {
+			u: PROOFSIZE as u32 - 1,
+			v: 0,
+			nonce: 0,
+		});
+		let sols = Graph::search(&edges, Variant::Cuckatoo).unwrap();
+		assert_eq!(sols.len(), MAX_SOLS);
+		for sol in &sols {
+			assert_eq!(sol.nonces.len(), PROOFSIZE);
+			assert_eq!(sol.nonces[0], 0);
+		}
+	}
+
+	#[test]
+	fn stopped_trimmer_returns_nothing() {
+		let k = set_header_nonce(&[0u8; 80], Some(68), true).unwrap();
+		let mut trimmer = Trimmer::new(Variant::Cuckatoo, EDGE_BITS as u8, 1);
+		assert!(trimmer.run(&k, &AtomicBool::new(true)).is_none());
+	}
+}
@@ -0,0 +1,70 @@
+/// Number of edges generated together by a Cuckaroo siphash block
+pub const EDGE_BLOCK_SIZE: u64 = 64;
+/// Mask of an edge's position within its block
+pub const EDGE_BLOCK_MASK: u64 = EDGE_BLOCK_SIZE - 1;
+
+/// Siphash-2-4 state, keyed straight from the header hash as Cuckoo does
+pub struct SipHash24(u64, u64, u64, u64);
+
+impl SipHash24 {
+	pub fn new(k: &[u64; 4]) -> SipHash24 {
+		SipHash24(k[0], k[1], k[2], k[3])
+	}
+
+	pub fn hash(&mut self, nonce: u64) {
+		self.3 ^= nonce;
+		self.round();
+		self.round();
+		self.0 ^= nonce;
+		self.2 ^= 0xff;
+		for _ in 0..4 {
+			self.round();
+		}
+	}
+
+	#[inline]
+	pub fn digest(&self) -> u64 {
+		(self.0 ^ self.1) ^ (self.2 ^ self.3)
+	}
+
+	#[inline]
+	fn round(&mut self) {
+		self.0 = self.0.wrapping_add(self.1);
+		self.2 = self.2.wrapping_add(self.3);
+		self.1 = self.1.rotate_left(13);
+		self.3 = self.3.rotate_left(16);
+		self.1 ^= self.0;
+		self.3 ^= self.2;
+		self.0 = self.0.rotate_left(32);
+		self.2 = self.2.wrapping_add(self.1);
+		self.0 = self.0.wrapping_add(self.3);
+		self.1 = self.1.rotate_left(17);
+		self.3 = self.3.rotate_left(21);
+		self.1 ^= self.2;
+		self.3 ^= self.0;
+		self.2 = self.2.rotate_left(32);
+	}
+}
+
+/// Single siphash of a nonce, as used by Cuckatoo
+pub fn siphash24(k: &[u64; 4], nonce: u64) -> u64 {
+	let mut siphash = SipHash24::new(k);
+	siphash.hash(nonce);
+	siphash.digest()
+}
+
+/// Hashes of a whole Cuckaroo block starting at `nonce0`, with every hash
+/// but the last xored with the last one
+pub fn siphash_block(k: &[u64; 4], nonce0: u64) -> [u64; EDGE_BLOCK_SIZE as usize] {
+	let mut block = [0u64; EDGE_BLOCK_SIZE as usize];
+	let mut siphash = SipHash24::new(k);
+	for (n, hash) in block.iter_mut().enumerate() {
+		siphash.hash(nonce0 + n as u64);
+		*hash = siphash.digest();
+	}
+	let last = block[EDGE_BLOCK_MASK as usize];
+	for hash in block[..EDGE_BLOCK_MASK as usize].iter_mut() {
+		*hash ^= last;
+	}
+	block
+}
@@ -0,0 +1,274 @@
+use hashbrown::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+
+use crate::Variant;
+use crate::siphash::{EDGE_BLOCK_SIZE, siphash_block, siphash24};
+
+/// Lean trimming stops, and the surviving edges are extracted into a list,
+/// once no more than 1 / 2^EXTRACT_SHIFT of the edges are left alive
+const EXTRACT_SHIFT: u8 = 6;
+
+/// An edge surviving trimming, with its two endpoints
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+	pub u: u32,
+	pub v: u32,
+	pub nonce: u32,
+}
+
+impl Edge {
+	#[inline]
+	fn node(&self, uorv: u32) -> u32 {
+		if uorv == 0 { self.u } else { self.v }
+	}
+}
+
+/// Lean CPU trimmer: one alive bit per edge (each 64 bit word lining up with
+/// a Cuckaroo siphash block) and two bits per node to count degrees
+pub struct Trimmer {
+	pub edge_bits: u8,
+	pub variant: Variant,
+	nthreads: usize,
+	alive: Vec<u64>,
+	once: Vec<AtomicU64>,
+	twice: Vec<AtomicU64>,
+}
+
+impl Trimmer {
+	pub fn new(variant: Variant, edge_bits: u8, nthreads: usize) -> Trimmer {
+		let words = (1usize << edge_bits) / EDGE_BLOCK_SIZE as usize;
+		Trimmer {
+			edge_bits,
+			variant,
+			nthreads: nthreads.max(1),
+			alive: vec![0; words],
+			once: (0..words).map(|_| AtomicU64::new(0)).collect(),
+			twice: (0..words).map(|_| AtomicU64::new(0)).collect(),
+		}
+	}
+
+	/// Trims the graph generated by the siphash keys down to the edges that
+	/// can still be part of a cycle, or returns None when stopped
+	pub fn run(&mut self, k: &[u64; 4], stop: &AtomicBool) -> Option<Vec<Edge>> {
+		let edge_count = 1u64 << self.edge_bits;
+		self.alive.iter_mut().for_each(|w| *w = !0);
+		let mut alive = edge_count;
+		let mut idle = 0;
+		let mut uorv = 0;
+		loop {
+			if stop.load(Ordering::Relaxed) {
+				return None;
+			}
+			self.count(k, uorv);
+			let left = self.trim(k, uorv);
+			idle = if left == alive { idle + 1 } else { 0 };
+			alive = left;
+			if alive <= edge_count >> EXTRACT_SHIFT || idle == 2 {
+				break;
+			}
+			uorv ^= 1;
+		}
+		let mut edges = self.extract(k);
+		trim_edges(&mut edges, self.variant, stop)?;
+		Some(edges)
+	}
+
+	#[inline]
+	fn edge_mask(&self) -> u64 {
+		(1u64 << self.edge_bits) - 1
+	}
+
+	#[inline]
+	fn chunk_len(&self) -> usize {
+		self.alive.len().div_ceil(self.nthreads)
+	}
+
+	/// Counts, up to two, the alive edges at each `uorv` node
+	fn count(&self, k: &[u64; 4], uorv: u32) {
+		for w in self.once.iter().chain(self.twice.iter()) {
+			w.store(0, Ordering::Relaxed);
+		}
+		let chunk = self.chunk_len();
+		let (variant, edge_mask) = (self.variant, self.edge_mask());
+		let (once, twice) = (&self.once, &self.twice);
+		thread::scope(|s| {
+			for (c, words) in self.alive.chunks(chunk).enumerate() {
+				s.spawn(move || {
+					for (i, bits) in words.iter().enumerate() {
+						nodes(
+							variant,
+							edge_mask,
+							k,
+							c * chunk + i,
+							*bits,
+							uorv,
+							|_, node| {
+								let (w, bit) = ((node / 64) as usize, 1u64 << (node % 64));
+								if once[w].fetch_or(bit, Ordering::Relaxed) & bit != 0 {
+									twice[w].fetch_or(bit, Ordering::Relaxed);
+								}
+							},
+						);
+					}
+				});
+			}
+		});
+	}
+
+	/// Kills the edges whose `uorv` endpoint has no other edge to continue a
+	/// cycle through, returning how many are left alive
+	fn trim(&mut self, k: &[u64; 4], uorv: u32) -> u64 {
+		let chunk = self.chunk_len();
+		let (variant, edge_mask) = (self.variant, self.edge_mask());
+		let (pair, degree) = adjacency(variant);
+		let counts = if degree == 1 { &self.once } else { &self.twice };
+		thread::scope(|s| {
+			let handles: Vec<_> = self
+				.alive
+				.chunks_mut(chunk)
+				.enumerate()
+				.map(|(c, words)| {
+					s.spawn(move || {
+						let mut left = 0;
+						for (i, bits) in words.iter_mut().enumerate() {
+							let mut kill = 0;
+							nodes(
+								variant,
+								edge_mask,
+								k,
+								c * chunk + i,
+								*bits,
+								uorv,
+								|nonce, node| {
+									let node = node ^ pair;
+									let bit = 1u64 << (node % 64);
+									if counts[(node / 64) as usize].load(Ordering::Relaxed) & bit
+										== 0
+									{
+										kill |= 1u64 << (nonce % EDGE_BLOCK_SIZE);
+									}
+								},
+							);
+							*bits &= !kill;
+							left += bits.count_ones() as u64;
+						}
+						left
+					})
+				})
+				.collect();
+			handles.into_iter().map(|h| h.join().unwrap()).sum()
+		})
+	}
+
+	/// Lists the alive edges with both their endpoints
+	fn extract(&self, k: &[u64; 4]) -> Vec<Edge> {
+		let mut edges = vec![];
+		let mut us = [0u32; EDGE_BLOCK_SIZE as usize];
+		for (word, bits) in self.alive.iter().enumerate() {
+			nodes(
+				self.variant,
+				self.edge_mask(),
+				k,
+				word,
+				*bits,
+				0,
+				|nonce, u| {
+					us[(nonce % EDGE_BLOCK_SIZE) as usize] = u;
+				},
+			);
+			nodes(
+				self.variant,
+				self.edge_mask(),
+				k,
+				word,
+				*bits,
+				1,
+				|nonce, v| {
+					edges.push(Edge {
+						u: us[(nonce % EDGE_BLOCK_SIZE) as usize],
+						v,
+						nonce: nonce as u32,
+					});
+				},
+			);
+		}
+		edges
+	}
+}
+
+/// How edges join up in a variant: a cycle continues from an edge's endpoint
+/// `node` through the edges at `node ^ pair`, so an edge lives while there
+/// are at least `degree` edges there (counting itself when `pair` is 0)
+fn adjacency(variant: Variant) -> (u32, u32) {
+	match variant {
+		Variant::Cuckatoo => (1, 1),
+		Variant::Cuckaroo => (0, 2),
+	}
+}
+
+/// Calls `f` with the nonce and the `uorv` endpoint of each alive edge in
+/// `bits`, the alive word at index `word`
+fn nodes<F: FnMut(u64, u32)>(
+	variant: Variant,
+	edge_mask: u64,
+	k: &[u64; 4],
+	word: usize,
+	mut bits: u64,
+	uorv: u32,
+	mut f: F,
+) {
+	if bits == 0 {
+		return;
+	}
+	let nonce0 = word as u64 * EDGE_BLOCK_SIZE;
+	match variant {
+		Variant::Cuckatoo => {
+			while bits != 0 {
+				let nonce = nonce0 + bits.trailing_zeros() as u64;
+				bits &= bits - 1;
+				f(
+					nonce,
+					(siphash24(k, 2 * nonce + uorv as u64) & edge_mask) as u32,
+				);
+			}
+		}
+		Variant::Cuckaroo => {
+			let block = siphash_block(k, nonce0);
+			while bits != 0 {
+				let i = bits.trailing_zeros() as usize;
+				bits &= bits - 1;
+				f(
+					nonce0 + i as u64,
+					((block[i] >> (32 * uorv)) & edge_mask) as u32,
+				);
+			}
+		}
+	}
+}
+
+/// Keeps trimming an extracted edge list until nothing more can be removed
+fn trim_edges(edges: &mut Vec<Edge>, variant: Variant, stop: &AtomicBool) -> Option<()> {
+	let (pair, degree) = adjacency(variant);
+	loop {
+		let before = edges.len();
+		for uorv in 0..2 {
+			if stop.load(Ordering::Relaxed) {
+				return None;
+			}
+			let mut counts: HashMap<u32, u32> =
+				HashMap::with_capacity_and_hasher(edges.len(), Default::default());
+			for e in edges.iter() {
+				*counts.entry(e.node(uorv)).or_insert(0) += 1;
+			}
+			edges.retain(|e| {
+				counts
+					.get(&(e.node(uorv) ^ pair))
+					.is_some_and(|c| *c >= degree)
+			});
+		}
+		if edges.len() == before {
+			return Some(());
+		}
+	}
+}
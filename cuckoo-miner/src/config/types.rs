@@ -32,6 +32,9 @@ pub struct PluginConfig {
 
 	/// device params
 	pub params: SolverParams,
+
+	/// percentage of the time the device should spend mining
+	pub duty_cycle: u8,
 }
 
 impl PluginConfig {
@@ -51,6 +54,7 @@ impl PluginConfig {
 				name: name.to_owned(),
 				file: plugin_file_str.to_owned(),
 				params,
+				duty_cycle: 100,
 			}
 		})
 	}
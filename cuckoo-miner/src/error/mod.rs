@@ -18,6 +18,8 @@
 use std::io;
 use std::string;
 
+use crate::miner::header::HeaderError;
+
 /// #Description
 ///
 /// Top level enum for all errors that the cuckoo-miner crate can return.
@@ -53,6 +55,9 @@ pub enum CuckooMinerError {
 
 	/// Error getting stats or stats not implemented
 	StatsError(String),
+
+	/// A job's header couldn't be decoded
+	InvalidHeaderError(String),
 }
 
 impl From<io::Error> for CuckooMinerError {
@@ -66,3 +71,9 @@ impl From<string::FromUtf8Error> for CuckooMinerError {
 		CuckooMinerError::PluginIOError(format!("Error loading plugin description: {}", error))
 	}
 }
+
+impl From<HeaderError> for CuckooMinerError {
+	fn from(error: HeaderError) -> Self {
+		CuckooMinerError::InvalidHeaderError(format!("Invalid job header: {}", error))
+	}
+}
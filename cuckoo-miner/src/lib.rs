@@ -35,6 +35,7 @@ extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
 
+extern crate arc_swap;
 extern crate blake2_rfc as blake2;
 extern crate byteorder;
 extern crate crypto;
@@ -49,6 +50,11 @@ extern crate slog;
 
 extern crate glob;
 
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "async")]
+extern crate tokio_stream;
+
 mod config;
 mod cuckoo_sys;
 mod error;
@@ -57,4 +63,8 @@ mod miner;
 pub use config::types::PluginConfig;
 pub use cuckoo_sys::ffi::PluginLibrary;
 pub use error::CuckooMinerError;
+#[cfg(feature = "async")]
+pub use miner::async_miner::{AsyncCuckooMiner, SolutionStream};
+pub use miner::header::{BlockHeader, HeaderError, PrePow};
 pub use miner::miner::CuckooMiner;
+pub use miner::types::{Job, JobSolutions, MinerEvent, NonceMode, RestartPolicy, WatchdogPolicy};
@@ -0,0 +1,185 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Async front-end to the miner, for callers running on tokio. Solvers
+//! still run on their own threads, this only takes care of waiting on them
+//! without blocking the runtime.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use crate::CuckooMinerError;
+use crate::miner::header::PrePow;
+use crate::miner::miner::CuckooMiner;
+use crate::miner::types::JobSolutions;
+use plugin::SolverStats;
+
+/// Stream of solutions, oldest first, each tagged with the job it was
+/// found for
+pub type SolutionStream = UnboundedReceiverStream<JobSolutions>;
+
+/// A CuckooMiner driven from async code
+pub struct AsyncCuckooMiner {
+	miner: Arc<Mutex<CuckooMiner>>,
+	solutions: Option<SolutionStream>,
+}
+
+impl AsyncCuckooMiner {
+	/// Wrap a miner, whose solvers may or may not have been started. From
+	/// now on its solutions only come out of the stream from `solutions`
+	pub fn new(mut miner: CuckooMiner) -> AsyncCuckooMiner {
+		let solutions_rx = miner.take_solutions();
+		let (solutions_tx, stream_rx) = mpsc::unbounded_channel();
+		// pass solutions on as they come in, until either end goes away
+		thread::spawn(move || {
+			for solutions in solutions_rx.iter() {
+				if solutions_tx.send(solutions).is_err() {
+					break;
+				}
+			}
+		});
+		AsyncCuckooMiner {
+			miner: Arc::new(Mutex::new(miner)),
+			solutions: Some(UnboundedReceiverStream::new(stream_rx)),
+		}
+	}
+
+	/// Starts solvers, ready for jobs
+	pub async fn start_solvers(&self) -> Result<(), CuckooMinerError> {
+		self.blocking(|miner| miner.start_solvers()).await
+	}
+
+	/// Hand solvers a new job, see `CuckooMiner::notify`
+	pub async fn notify(
+		&self,
+		job_id: u32,
+		height: u64,
+		pre_pow: PrePow,
+		difficulty: u64,
+	) -> Result<(), CuckooMinerError> {
+		self.blocking(move |miner| miner.notify(job_id, height, pre_pow, difficulty))
+			.await
+	}
+
+	/// Change the target difficulty of the current job
+	pub async fn set_difficulty(&self, difficulty: u64) -> Result<(), CuckooMinerError> {
+		self.blocking(move |miner| miner.set_difficulty(difficulty))
+			.await
+	}
+
+	/// Set the hex-encoded extranonce the pool assigned us
+	pub async fn set_extranonce(&self, extranonce: &str) -> Result<(), CuckooMinerError> {
+		let extranonce = extranonce.to_owned();
+		self.blocking(move |miner| miner.set_extranonce(&extranonce))
+			.await
+	}
+
+	/// get stats for all running solvers
+	pub async fn get_stats(&self) -> Result<Vec<SolverStats>, CuckooMinerError> {
+		self.blocking(|miner| miner.get_stats()).await
+	}
+
+	/// The stream of solutions found by all solvers. There's only one, so
+	/// this returns None after the first call
+	pub fn solutions(&mut self) -> Option<SolutionStream> {
+		self.solutions.take()
+	}
+
+	/// Stops all solvers, resolving once they've exited or the shutdown
+	/// timeout is up
+	pub async fn shutdown(self) -> Result<(), CuckooMinerError> {
+		self.blocking(|miner| {
+			miner.stop_solvers();
+			miner.wait_for_solver_shutdown();
+			Ok(())
+		})
+		.await
+	}
+
+	/// Run a call into the miner on the blocking thread pool, as it may
+	/// wait on solver threads
+	async fn blocking<F, T>(&self, f: F) -> Result<T, CuckooMinerError>
+	where
+		F: FnOnce(&mut CuckooMiner) -> Result<T, CuckooMinerError> + Send + 'static,
+		T: Send + 'static,
+	{
+		let miner = self.miner.clone();
+		task::spawn_blocking(move || f(&mut miner.lock().unwrap()))
+			.await
+			.map_err(|e| CuckooMinerError::PluginProcessingError(format!("{}", e)))?
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use plugin::SolverSolutions;
+	use std::time::Duration;
+	use tokio::time::timeout;
+	use tokio_stream::StreamExt;
+
+	#[tokio::test]
+	async fn notify_stream_solutions_and_shut_down() {
+		let miner = CuckooMiner::new(vec![]);
+		let solutions_tx = miner.solutions_sender();
+		let mut miner = AsyncCuckooMiner::new(miner);
+		let mut solutions = miner.solutions().unwrap();
+		assert!(miner.solutions().is_none());
+		miner.start_solvers().await.unwrap();
+
+		let pre_pow = PrePow {
+			height: 100,
+			..Default::default()
+		};
+		miner.notify(7, 100, pre_pow.clone(), 16).await.unwrap();
+		{
+			let job = miner.miner.lock().unwrap().current_job();
+			assert_eq!((job.job_id, job.height, job.difficulty), (7, 100, 16));
+			assert_eq!(job.pre_pow, pre_pow);
+		}
+
+		// as a solver thread would
+		for job_id in [7, 8] {
+			solutions_tx
+				.send(JobSolutions {
+					job_id,
+					height: 100,
+					solutions: SolverSolutions::default(),
+				})
+				.unwrap();
+		}
+		for job_id in [7, 8] {
+			let found = timeout(Duration::from_secs(10), solutions.next())
+				.await
+				.unwrap()
+				.unwrap();
+			assert_eq!((found.job_id, found.height), (job_id, 100));
+		}
+
+		// shutting down waits on solver threads off the runtime, which
+		// keeps running other tasks meanwhile
+		let ticker = tokio::spawn(async {
+			tokio::task::yield_now().await;
+		});
+		timeout(Duration::from_secs(10), miner.shutdown())
+			.await
+			.unwrap()
+			.unwrap();
+		ticker.await.unwrap();
+	}
+}
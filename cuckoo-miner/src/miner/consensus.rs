@@ -13,13 +13,22 @@
 // limitations under the License.
 
 /// Difficulty calculation as from Grin
-use blake2::blake2b::Blake2b;
-use byteorder::{BigEndian, ByteOrder};
+use blake2::blake2b::{Blake2b, blake2b};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use std::cmp::{max, min};
 use std::fmt;
 
 // constants from grin
 const PROOF_SIZE: usize = 42;
+const EDGE_BLOCK_SIZE: u64 = 64;
+const EDGE_BLOCK_MASK: u64 = EDGE_BLOCK_SIZE - 1;
+const BASE_EDGE_BITS: u8 = 24;
+const DEFAULT_MIN_EDGE_BITS: u8 = 31;
+/// Graph weight of C31 at launch, which grin's stratum server scales the
+/// share target of primary proofs by
+const PRIMARY_SHARE_SCALE: u64 = 7936;
+const WEEK_HEIGHT: u64 = 7 * 24 * 60;
+const YEAR_HEIGHT: u64 = 52 * WEEK_HEIGHT;
 
 /// The difficulty is defined as the maximum target divided by the block hash.
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
@@ -34,9 +43,9 @@ impl Difficulty {
 		Difficulty { num: max(num, 1) }
 	}
 
-	/// unscaled proof
-	fn from_proof_unscaled(proof: &Proof) -> Difficulty {
-		Difficulty::from_num(proof.scaled_difficulty(1u64))
+	/// proof scaled by the given factor
+	fn from_proof_scaled(proof: &Proof, scale: u64) -> Difficulty {
+		Difficulty::from_num(proof.scaled_difficulty(scale))
 	}
 
 	/// Converts the difficulty into a u64
@@ -108,12 +117,392 @@ impl Proof {
 		Hash(ret)
 	}
 
-	/// unscaled difficulty
-	pub fn to_difficulty_unscaled(&self) -> Difficulty {
-		Difficulty::from_proof_unscaled(&self)
+	/// Whether the proof makes a share at the pool's unscaled target, checked
+	/// the way grin's stratum server does. Primary proofs are scaled by their
+	/// graph weight against the target scaled by that of C31. Other sizes,
+	/// such as the small graphs test setups mine, are compared unscaled
+	pub fn meets_share_target(&self, height: u64, target: u64) -> bool {
+		if self.edge_bits >= DEFAULT_MIN_EDGE_BITS {
+			let scale = graph_weight(height, self.edge_bits);
+			Difficulty::from_proof_scaled(self, scale).to_num()
+				>= target.saturating_mul(PRIMARY_SHARE_SCALE)
+		} else {
+			// The pool scales secondary (C29) proofs and the target alike by
+			// the header's secondary scaling, which cancels out exactly, so
+			// they're compared unscaled
+			Difficulty::from_proof_scaled(self, 1).to_num() >= target
+		}
+	}
+
+	/// Checks that the nonces are edges forming a single cycle through the
+	/// graph generated from the header (nonce included) under the variant
+	pub fn verify(&self, header: &[u8], variant: Variant) -> Result<(), ProofError> {
+		if self.nonces.len() != PROOF_SIZE || self.edge_bits == 0 || self.edge_bits > 63 {
+			return Err(ProofError::WrongSize);
+		}
+		let edge_mask = (1u64 << self.edge_bits) - 1;
+		for (n, nonce) in self.nonces.iter().enumerate() {
+			if *nonce > edge_mask {
+				return Err(ProofError::TooBig);
+			}
+			if n > 0 && *nonce <= self.nonces[n - 1] {
+				return Err(ProofError::TooSmall);
+			}
+		}
+		let keys = siphash_keys(header);
+		match variant {
+			Variant::Cuckatoo => verify_cuckatoo(&keys, &self.nonces, edge_mask),
+			Variant::Cuckaroo => verify_cuckaroo(&keys, &self.nonces, edge_mask),
+			Variant::Cuckarood => verify_cuckarood(&keys, &self.nonces, edge_mask),
+			Variant::Cuckaroom => verify_cuckaroom(&keys, &self.nonces, edge_mask),
+			Variant::Cuckarooz => verify_cuckarooz(&keys, &self.nonces, edge_mask),
+		}
+	}
+}
+
+/// Weight of a primary proof of the given graph size, as in grin. C31 is
+/// phased out over the weeks following the first year, leaving C32 and up
+pub fn graph_weight(height: u64, edge_bits: u8) -> u64 {
+	let mut xpr_edge_bits = edge_bits as u64;
+	if edge_bits == 31 && height >= YEAR_HEIGHT {
+		xpr_edge_bits = xpr_edge_bits.saturating_sub(1 + (height - YEAR_HEIGHT) / WEEK_HEIGHT);
+	}
+	(2u64 << edge_bits.saturating_sub(BASE_EDGE_BITS)) * xpr_edge_bits
+}
+
+/// The Cuckoo Cycle PoW variants, which differ in how the graph's edges are
+/// generated from the header and in how they join up into cycles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+	/// ASIC friendly Cuckatoo
+	Cuckatoo,
+	/// The original Cuckaroo
+	Cuckaroo,
+	/// Cuckarood, with edges alternating direction around the cycle
+	Cuckarood,
+	/// Cuckaroom, a directed cycle on a single set of nodes
+	Cuckaroom,
+	/// Cuckarooz, an undirected cycle on a single set of nodes
+	Cuckarooz,
+}
+
+impl Variant {
+	/// The variant a plugin solves, going by the usual plugin names such as
+	/// `cuckarooz_cuda_29` or `ocl_cuckatoo`
+	pub fn from_plugin_name(name: &str) -> Option<Variant> {
+		let name = name.to_lowercase();
+		// most specific first, as they all start with "cuckaroo"
+		[
+			("cuckarooz", Variant::Cuckarooz),
+			("cuckaroom", Variant::Cuckaroom),
+			("cuckarood", Variant::Cuckarood),
+			("cuckaroo", Variant::Cuckaroo),
+			("cuckatoo", Variant::Cuckatoo),
+		]
+		.iter()
+		.find(|(prefix, _)| name.contains(prefix))
+		.map(|(_, variant)| *variant)
+	}
+}
+
+/// Why a proof failed verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofError {
+	/// Not the proof size's worth of nonces
+	WrongSize,
+	/// A nonce past the last edge of the graph
+	TooBig,
+	/// Nonces not in strictly ascending order
+	TooSmall,
+	/// Cuckarood edges not evenly split between both directions
+	Unbalanced,
+	/// The edges' endpoints can't all pair up
+	EndpointsMismatch,
+	/// More than two edges meet at a node of the cycle
+	Branch,
+	/// An edge that no other edge continues from
+	DeadEnd,
+	/// The cycle closes without going through every edge
+	TooShort,
+}
+
+impl fmt::Display for ProofError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let reason = match self {
+			ProofError::WrongSize => "wrong cycle length",
+			ProofError::TooBig => "edge too big",
+			ProofError::TooSmall => "edges not ascending",
+			ProofError::Unbalanced => "edges not balanced",
+			ProofError::EndpointsMismatch => "endpoints don't match up",
+			ProofError::Branch => "branch in cycle",
+			ProofError::DeadEnd => "cycle dead ends",
+			ProofError::TooShort => "cycle too short",
+		};
+		write!(f, "{}", reason)
 	}
 }
 
+fn verify_cuckatoo(keys: &[u64; 4], nonces: &[u64], edge_mask: u64) -> Result<(), ProofError> {
+	let mut uvs = vec![0u64; 2 * PROOF_SIZE];
+	let mut xor0 = (PROOF_SIZE as u64 / 2) & 1;
+	let mut xor1 = xor0;
+	for (n, nonce) in nonces.iter().enumerate() {
+		uvs[2 * n] = siphash24(keys, 2 * nonce) & edge_mask;
+		uvs[2 * n + 1] = siphash24(keys, 2 * nonce + 1) & edge_mask;
+		xor0 ^= uvs[2 * n];
+		xor1 ^= uvs[2 * n + 1];
+	}
+	if xor0 | xor1 != 0 {
+		return Err(ProofError::EndpointsMismatch);
+	}
+	// edges join at nodes differing only in the lowest bit
+	follow_cycle(&uvs, 2, |a, b| a >> 1 == b >> 1, true)
+}
+
+fn verify_cuckaroo(keys: &[u64; 4], nonces: &[u64], edge_mask: u64) -> Result<(), ProofError> {
+	let mut uvs = vec![0u64; 2 * PROOF_SIZE];
+	let (mut xor0, mut xor1) = (0, 0);
+	for (n, nonce) in nonces.iter().enumerate() {
+		let edge = siphash_block(keys, *nonce, 21, false);
+		uvs[2 * n] = edge & edge_mask;
+		uvs[2 * n + 1] = (edge >> 32) & edge_mask;
+		xor0 ^= uvs[2 * n];
+		xor1 ^= uvs[2 * n + 1];
+	}
+	if xor0 | xor1 != 0 {
+		return Err(ProofError::EndpointsMismatch);
+	}
+	follow_cycle(&uvs, 2, |a, b| a == b, false)
+}
+
+fn verify_cuckarood(keys: &[u64; 4], nonces: &[u64], edge_mask: u64) -> Result<(), ProofError> {
+	let node_mask = edge_mask >> 1;
+	let mut uvs = vec![0u64; 2 * PROOF_SIZE];
+	let mut ndir = [0usize; 2];
+	let (mut xor0, mut xor1) = (0, 0);
+	for nonce in nonces {
+		// even and odd nonces are edges in opposite directions, which take
+		// turns around the cycle
+		let dir = (nonce & 1) as usize;
+		if ndir[dir] >= PROOF_SIZE / 2 {
+			return Err(ProofError::Unbalanced);
+		}
+		let edge = siphash_block(keys, *nonce, 25, false);
+		let idx = 4 * ndir[dir] + 2 * dir;
+		uvs[idx] = edge & node_mask;
+		uvs[idx + 1] = (edge >> 32) & node_mask;
+		xor0 ^= uvs[idx];
+		xor1 ^= uvs[idx + 1];
+		ndir[dir] += 1;
+	}
+	if xor0 | xor1 != 0 {
+		return Err(ProofError::EndpointsMismatch);
+	}
+	let (mut n, mut i) = (0, 0);
+	loop {
+		// an edge continues through an edge of the other direction
+		let mut j = i;
+		for k in (((i % 4) ^ 2)..(2 * PROOF_SIZE)).step_by(4) {
+			if uvs[k] == uvs[i] {
+				if j != i {
+					return Err(ProofError::Branch);
+				}
+				j = k;
+			}
+		}
+		if j == i {
+			return Err(ProofError::DeadEnd);
+		}
+		i = j ^ 1;
+		n += 1;
+		if i == 0 {
+			break;
+		}
+	}
+	if n == PROOF_SIZE {
+		Ok(())
+	} else {
+		Err(ProofError::TooShort)
+	}
+}
+
+fn verify_cuckaroom(keys: &[u64; 4], nonces: &[u64], edge_mask: u64) -> Result<(), ProofError> {
+	let mut from = vec![0u64; PROOF_SIZE];
+	let mut to = vec![0u64; PROOF_SIZE];
+	let (mut xor_from, mut xor_to) = (0, 0);
+	for (n, nonce) in nonces.iter().enumerate() {
+		let edge = siphash_block(keys, *nonce, 21, true);
+		from[n] = edge & edge_mask;
+		to[n] = (edge >> 32) & edge_mask;
+		xor_from ^= from[n];
+		xor_to ^= to[n];
+	}
+	if xor_from != xor_to {
+		return Err(ProofError::EndpointsMismatch);
+	}
+	let mut visited = [false; PROOF_SIZE];
+	let (mut n, mut i) = (0, 0);
+	loop {
+		if visited[i] {
+			return Err(ProofError::Branch);
+		}
+		visited[i] = true;
+		// the next edge leaves from where this one arrives
+		i = match from.iter().position(|f| *f == to[i]) {
+			Some(next) => next,
+			None => return Err(ProofError::DeadEnd),
+		};
+		n += 1;
+		if i == 0 {
+			break;
+		}
+	}
+	if n == PROOF_SIZE {
+		Ok(())
+	} else {
+		Err(ProofError::TooShort)
+	}
+}
+
+fn verify_cuckarooz(keys: &[u64; 4], nonces: &[u64], edge_mask: u64) -> Result<(), ProofError> {
+	let node_mask = (edge_mask << 1) | 1;
+	let mut uvs = vec![0u64; 2 * PROOF_SIZE];
+	let mut xor = 0;
+	for (n, nonce) in nonces.iter().enumerate() {
+		let edge = siphash_block(keys, *nonce, 21, true);
+		uvs[2 * n] = edge & node_mask;
+		uvs[2 * n + 1] = (edge >> 32) & node_mask;
+		xor ^= uvs[2 * n] ^ uvs[2 * n + 1];
+	}
+	if xor != 0 {
+		return Err(ProofError::EndpointsMismatch);
+	}
+	// either endpoint of an edge can meet either endpoint of the next one
+	follow_cycle(&uvs, 1, |a, b| a == b, false)
+}
+
+/// Follows the cycle through the edges' endpoints `uvs`, kept in pairs, from
+/// the first edge on. Endpoints `step` apart are compared, and each must
+/// `join` exactly one other. For Cuckatoo the two endpoints joining must also
+/// be `distinct`.
+fn follow_cycle<F>(uvs: &[u64], step: usize, join: F, distinct: bool) -> Result<(), ProofError>
+where
+	F: Fn(u64, u64) -> bool,
+{
+	let size = uvs.len();
+	let (mut n, mut i) = (0, 0);
+	loop {
+		let mut j = i;
+		let mut k = i;
+		loop {
+			k = (k + step) % size;
+			if k == i {
+				break;
+			}
+			if join(uvs[k], uvs[i]) {
+				if j != i {
+					return Err(ProofError::Branch);
+				}
+				j = k;
+			}
+		}
+		if j == i || (distinct && uvs[j] == uvs[i]) {
+			return Err(ProofError::DeadEnd);
+		}
+		i = j ^ 1;
+		n += 1;
+		if i == 0 {
+			break;
+		}
+	}
+	if n == size / 2 {
+		Ok(())
+	} else {
+		Err(ProofError::TooShort)
+	}
+}
+
+/// Siphash keys for a header, as its blake2b hash
+fn siphash_keys(header: &[u8]) -> [u64; 4] {
+	let hash = blake2b(32, &[], header);
+	let h = hash.as_bytes();
+	[
+		LittleEndian::read_u64(&h[0..8]),
+		LittleEndian::read_u64(&h[8..16]),
+		LittleEndian::read_u64(&h[16..24]),
+		LittleEndian::read_u64(&h[24..32]),
+	]
+}
+
+/// Siphash-2-4 keyed directly with the 4 keys, with the rotation that
+/// differs between variants
+struct SipHash24(u64, u64, u64, u64);
+
+impl SipHash24 {
+	fn new(keys: &[u64; 4]) -> SipHash24 {
+		SipHash24(keys[0], keys[1], keys[2], keys[3])
+	}
+
+	fn hash(&mut self, nonce: u64, rot_e: u32) {
+		self.3 ^= nonce;
+		self.round(rot_e);
+		self.round(rot_e);
+		self.0 ^= nonce;
+		self.2 ^= 0xff;
+		for _ in 0..4 {
+			self.round(rot_e);
+		}
+	}
+
+	fn digest(&self) -> u64 {
+		(self.0 ^ self.1) ^ (self.2 ^ self.3)
+	}
+
+	fn round(&mut self, rot_e: u32) {
+		self.0 = self.0.wrapping_add(self.1);
+		self.2 = self.2.wrapping_add(self.3);
+		self.1 = self.1.rotate_left(13);
+		self.3 = self.3.rotate_left(16);
+		self.1 ^= self.0;
+		self.3 ^= self.2;
+		self.0 = self.0.rotate_left(32);
+		self.2 = self.2.wrapping_add(self.1);
+		self.0 = self.0.wrapping_add(self.3);
+		self.1 = self.1.rotate_left(17);
+		self.3 = self.3.rotate_left(rot_e);
+		self.1 ^= self.2;
+		self.3 ^= self.0;
+		self.2 = self.2.rotate_left(32);
+	}
+}
+
+fn siphash24(keys: &[u64; 4], nonce: u64) -> u64 {
+	let mut siphash = SipHash24::new(keys);
+	siphash.hash(nonce, 21);
+	siphash.digest()
+}
+
+/// The Cuckaroo family's edge for a nonce, out of the hashes of its whole
+/// block of 64, xored with the block's last hash or with all later ones
+fn siphash_block(keys: &[u64; 4], nonce: u64, rot_e: u32, xor_all: bool) -> u64 {
+	let nonce0 = nonce & !EDGE_BLOCK_MASK;
+	let nonce_i = nonce & EDGE_BLOCK_MASK;
+	let mut hashes = [0u64; EDGE_BLOCK_SIZE as usize];
+	let mut siphash = SipHash24::new(keys);
+	for (n, hash) in hashes.iter_mut().enumerate() {
+		siphash.hash(nonce0 + n as u64, rot_e);
+		*hash = siphash.digest();
+	}
+	let xor_from = if xor_all || nonce_i == EDGE_BLOCK_MASK {
+		nonce_i + 1
+	} else {
+		EDGE_BLOCK_MASK
+	};
+	hashes[xor_from as usize..]
+		.iter()
+		.fold(hashes[nonce_i as usize], |xor, hash| xor ^ hash)
+}
+
 struct BitVec {
 	bits: Vec<u8>,
 }
@@ -166,3 +555,208 @@ impl fmt::Display for Hash {
 		fmt::Debug::fmt(self, f)
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	// solutions from grin's test vectors, for an all zero 80 byte header with
+	// the given nonce in its last 4 bytes
+	const CUCKATOO_29_NONCE: u32 = 20;
+	const CUCKATOO_29_SOL: [u64; PROOF_SIZE] = [
+		0x48a9e2, 0x9cf043, 0x155ca30, 0x18f4783, 0x248f86c, 0x2629a64, 0x5bad752, 0x72e3569,
+		0x93db760, 0x97d3b37, 0x9e05670, 0xa315d5a, 0xa3571a1, 0xa48db46, 0xa7796b6, 0xac43611,
+		0xb64912f, 0xbb6c71e, 0xbcc8be1, 0xc38a43a, 0xd4faa99, 0xe018a66, 0xe37e49c, 0xfa975fa,
+		0x11786035, 0x1243b60a, 0x12892da0, 0x141b5453, 0x1483c3a0, 0x1505525e, 0x1607352c,
+		0x16181fe3, 0x17e3a1da, 0x180b651e, 0x1899d678, 0x1931b0bb, 0x19606448, 0x1b041655,
+		0x1b2c20ad, 0x1bd7a83c, 0x1c05d5b0, 0x1c0b9caa,
+	];
+	const CUCKAROO_19_NONCE: u32 = 71;
+	const CUCKAROO_19_SOL: [u64; PROOF_SIZE] = [
+		0x45e9, 0x6a59, 0xf1ad, 0x10ef7, 0x129e8, 0x13e58, 0x17936, 0x19f7f, 0x208df, 0x23704,
+		0x24564, 0x27e64, 0x2b828, 0x2bb41, 0x2ffc0, 0x304c5, 0x31f2a, 0x347de, 0x39686, 0x3ab6c,
+		0x429ad, 0x45254, 0x49200, 0x4f8f8, 0x5697f, 0x57ad1, 0x5dd47, 0x607f8, 0x66199, 0x686c7,
+		0x6d5f3, 0x6da7a, 0x6dbdf, 0x6f6bf, 0x6ffbb, 0x7580e, 0x78594, 0x785ac, 0x78b1d, 0x7b80d,
+		0x7c11c, 0x7da35,
+	];
+	const CUCKAROOD_19_NONCE: u32 = 64;
+	const CUCKAROOD_19_SOL: [u64; PROOF_SIZE] = [
+		0xa00, 0x3ffb, 0xa474, 0xdc27, 0x182e6, 0x242cc, 0x24de4, 0x270a2, 0x28356, 0x2951f,
+		0x2a6ae, 0x2c889, 0x355c7, 0x3863b, 0x3bd7e, 0x3cdbc, 0x3ff95, 0x430b6, 0x4ba1a, 0x4bd7e,
+		0x4c59f, 0x4f76d, 0x52064, 0x5378c, 0x540a3, 0x5af6b, 0x5b041, 0x5e9d3, 0x64ec7, 0x6564b,
+		0x66763, 0x66899, 0x66e80, 0x68e4e, 0x69133, 0x6b20a, 0x6c2d7, 0x6fd3b, 0x79a8a, 0x79e29,
+		0x7ae52, 0x7defe,
+	];
+	const CUCKAROOM_19_NONCE: u32 = 37;
+	const CUCKAROOM_19_SOL: [u64; PROOF_SIZE] = [
+		0x0413c, 0x05121, 0x0546e, 0x1293a, 0x1dd27, 0x1e13e, 0x1e1d2, 0x22870, 0x24642, 0x24833,
+		0x29190, 0x2a732, 0x2ccf6, 0x302cf, 0x32d9a, 0x33700, 0x33a20, 0x351d9, 0x3554b, 0x35a70,
+		0x376c1, 0x398c6, 0x3f404, 0x3ff0c, 0x48b26, 0x49a03, 0x4c555, 0x4dcda, 0x4dfcd, 0x4fbb6,
+		0x50275, 0x584a8, 0x5da0d, 0x5dbf1, 0x6038f, 0x66540, 0x72bbd, 0x77323, 0x77424, 0x77a14,
+		0x77dc9, 0x7d9dc,
+	];
+	const CUCKAROOZ_19_NONCE: u32 = 75;
+	const CUCKAROOZ_19_SOL: [u64; PROOF_SIZE] = [
+		0x33b6, 0x487b, 0x88b7, 0x10bf6, 0x15144, 0x17cb7, 0x22621, 0x2358e, 0x23775, 0x24fb3,
+		0x26b8a, 0x2876c, 0x2973e, 0x2f4ba, 0x30a62, 0x3a36b, 0x3ba5d, 0x3be67, 0x3ec56, 0x43141,
+		0x4b9c5, 0x4fa06, 0x51a5c, 0x523e5, 0x53d08, 0x57d34, 0x5c2de, 0x60bba, 0x62509, 0x64d69,
+		0x6803f, 0x68af4, 0x6bd52, 0x6f041, 0x6f900, 0x70051, 0x7097d, 0x735e8, 0x742c2, 0x79ae5,
+		0x7f64d, 0x7fd49,
+	];
+
+	fn header(nonce: u32) -> Vec<u8> {
+		let mut header = vec![0u8; 80];
+		LittleEndian::write_u32(&mut header[76..], nonce);
+		header
+	}
+
+	fn vectors() -> Vec<(Variant, u8, u32, &'static [u64])> {
+		vec![
+			(Variant::Cuckatoo, 29, CUCKATOO_29_NONCE, &CUCKATOO_29_SOL),
+			(Variant::Cuckaroo, 19, CUCKAROO_19_NONCE, &CUCKAROO_19_SOL),
+			(
+				Variant::Cuckarood,
+				19,
+				CUCKAROOD_19_NONCE,
+				&CUCKAROOD_19_SOL,
+			),
+			(
+				Variant::Cuckaroom,
+				19,
+				CUCKAROOM_19_NONCE,
+				&CUCKAROOM_19_SOL,
+			),
+			(
+				Variant::Cuckarooz,
+				19,
+				CUCKAROOZ_19_NONCE,
+				&CUCKAROOZ_19_SOL,
+			),
+		]
+	}
+
+	fn proof(edge_bits: u8, nonces: &[u64]) -> Proof {
+		Proof {
+			edge_bits,
+			nonces: nonces.to_vec(),
+		}
+	}
+
+	#[test]
+	fn verifies_known_solutions() {
+		for (variant, edge_bits, nonce, sol) in vectors() {
+			assert_eq!(
+				proof(edge_bits, sol).verify(&header(nonce), variant),
+				Ok(()),
+				"{:?}",
+				variant
+			);
+		}
+	}
+
+	#[test]
+	fn rejects_solutions_for_another_header_or_variant() {
+		for (variant, edge_bits, nonce, sol) in vectors() {
+			let p = proof(edge_bits, sol);
+			assert!(p.verify(&header(nonce + 1), variant).is_err());
+			for (other, _, _, _) in vectors() {
+				if other != variant {
+					assert!(p.verify(&header(nonce), other).is_err());
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn rejects_malformed_proofs() {
+		let h = header(CUCKAROO_19_NONCE);
+		let verify = |nonces: &[u64]| proof(19, nonces).verify(&h, Variant::Cuckaroo);
+
+		assert_eq!(verify(&CUCKAROO_19_SOL[1..]), Err(ProofError::WrongSize));
+
+		let mut nonces = CUCKAROO_19_SOL;
+		nonces.swap(3, 4);
+		assert_eq!(verify(&nonces), Err(ProofError::TooSmall));
+
+		let mut nonces = CUCKAROO_19_SOL;
+		nonces[PROOF_SIZE - 1] = 1 << 19;
+		assert_eq!(verify(&nonces), Err(ProofError::TooBig));
+
+		let mut nonces = CUCKAROO_19_SOL;
+		nonces[0] += 1;
+		assert!(verify(&nonces).is_err());
+	}
+
+	#[test]
+	fn rejects_unbalanced_cuckarood() {
+		let mut nonces = CUCKAROOD_19_SOL;
+		// flip one edge's direction
+		let i = nonces.iter().position(|n| n & 1 == 0).unwrap();
+		nonces[i] += 1;
+		assert_eq!(
+			proof(19, &nonces).verify(&header(CUCKAROOD_19_NONCE), Variant::Cuckarood),
+			Err(ProofError::Unbalanced)
+		);
+	}
+
+	#[test]
+	fn variant_from_plugin_name() {
+		let names = [
+			("cuckatoo_lean_cuda_31", Some(Variant::Cuckatoo)),
+			("ocl_cuckatoo", Some(Variant::Cuckatoo)),
+			("cuckaroo_cpu_compat_19", Some(Variant::Cuckaroo)),
+			("cuckarood_cuda_29", Some(Variant::Cuckarood)),
+			("cuckaroom_cuda_29", Some(Variant::Cuckaroom)),
+			("cuckarooz_cuda_29", Some(Variant::Cuckarooz)),
+			("my_plugin", None),
+		];
+		for (name, variant) in names.iter() {
+			assert_eq!(Variant::from_plugin_name(name), *variant, "{}", name);
+		}
+	}
+
+	#[test]
+	fn graph_weight_phases_out_c31() {
+		assert_eq!(graph_weight(0, 29), 1856);
+		assert_eq!(graph_weight(0, 31), 7936);
+		assert_eq!(graph_weight(YEAR_HEIGHT, 31), 256 * 30);
+		assert_eq!(graph_weight(YEAR_HEIGHT + 30 * WEEK_HEIGHT, 31), 0);
+		assert_eq!(graph_weight(YEAR_HEIGHT + 30 * WEEK_HEIGHT, 32), 16384);
+	}
+
+	#[test]
+	fn share_target_is_checked_like_the_pool() {
+		let secondary = Proof {
+			edge_bits: 29,
+			nonces: CUCKATOO_29_SOL.to_vec(),
+		};
+		let unscaled = secondary.scaled_difficulty(1);
+		assert!(secondary.meets_share_target(0, 1));
+		assert!(secondary.meets_share_target(0, unscaled));
+		assert!(!secondary.meets_share_target(0, unscaled + 1));
+
+		let primary = Proof {
+			edge_bits: 31,
+			nonces: CUCKATOO_29_SOL.to_vec(),
+		};
+		let unscaled = primary.scaled_difficulty(1);
+		assert!(primary.meets_share_target(0, unscaled));
+		assert!(!primary.meets_share_target(0, unscaled + 1));
+		// C32 weighs twice as much as C31 did
+		let c32 = Proof {
+			edge_bits: 32,
+			nonces: CUCKATOO_29_SOL.to_vec(),
+		};
+		assert!(c32.meets_share_target(0, 2 * unscaled));
+		// phased out C31 makes no shares at all
+		assert!(!primary.meets_share_target(YEAR_HEIGHT + 30 * WEEK_HEIGHT, 1));
+		// graphs the pool doesn't scale, like small test ones, count unscaled
+		let small = Proof {
+			edge_bits: 19,
+			nonces: CUCKATOO_29_SOL.to_vec(),
+		};
+		let unscaled = small.scaled_difficulty(1);
+		assert!(small.meets_share_target(0, unscaled));
+		assert!(!small.meets_share_target(0, unscaled + 1));
+	}
+}
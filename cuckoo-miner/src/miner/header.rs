@@ -0,0 +1,238 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The parts of a Grin block header the miner works with, as serialized
+//! by grin for proof of work
+
+use byteorder::{BigEndian, ByteOrder};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::miner::util::from_hex_string;
+
+/// Size of a serialized pre-pow, which is also where the nonce goes in
+/// the header
+pub const PRE_POW_SIZE: usize = 2 + 8 + 8 + 6 * 32 + 8 + 8 + 8 + 4;
+
+/// Why a pre-pow couldn't be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+	/// Not a hex string
+	InvalidHex,
+	/// Decoded to the given number of bytes instead of `PRE_POW_SIZE`
+	WrongSize(usize),
+}
+
+impl fmt::Display for HeaderError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			HeaderError::InvalidHex => write!(f, "pre-pow isn't valid hex"),
+			HeaderError::WrongSize(n) => {
+				write!(f, "pre-pow is {} bytes long, expected {}", n, PRE_POW_SIZE)
+			}
+		}
+	}
+}
+
+/// Everything in a block header before the nonce, which together with the
+/// nonce is what solvers hash to build their graphs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrePow {
+	/// Header version
+	pub version: u16,
+	/// Height of the block
+	pub height: u64,
+	/// Block timestamp, in seconds since the epoch
+	pub timestamp: i64,
+	/// Hash of the previous block
+	pub prev_hash: [u8; 32],
+	/// Root of the header MMR as of the previous block
+	pub prev_root: [u8; 32],
+	/// Root of the output MMR
+	pub output_root: [u8; 32],
+	/// Root of the range proof MMR
+	pub range_proof_root: [u8; 32],
+	/// Root of the kernel MMR
+	pub kernel_root: [u8; 32],
+	/// Total kernel offset up to this block
+	pub total_kernel_offset: [u8; 32],
+	/// Size of the output MMR
+	pub output_mmr_size: u64,
+	/// Size of the kernel MMR
+	pub kernel_mmr_size: u64,
+	/// Total difficulty of the chain up to and including this block
+	pub total_difficulty: u64,
+	/// Scaling factor of secondary (C29) proofs. Shares don't need it, as
+	/// the pool applies it to both the proof and the target
+	pub secondary_scaling: u32,
+}
+
+impl PrePow {
+	/// Decode a pre-pow from the hex string pools send it as
+	pub fn from_hex(pre_pow: &str) -> Result<PrePow, HeaderError> {
+		let bytes = from_hex_string(pre_pow).ok_or(HeaderError::InvalidHex)?;
+		PrePow::from_bytes(&bytes)
+	}
+
+	/// Decode a serialized pre-pow
+	pub fn from_bytes(bytes: &[u8]) -> Result<PrePow, HeaderError> {
+		if bytes.len() != PRE_POW_SIZE {
+			return Err(HeaderError::WrongSize(bytes.len()));
+		}
+		let mut pos = 0;
+		let mut take = move |n: usize| {
+			pos += n;
+			&bytes[pos - n..pos]
+		};
+		let hash = |b: &[u8]| {
+			let mut h = [0; 32];
+			h.copy_from_slice(b);
+			h
+		};
+		Ok(PrePow {
+			version: BigEndian::read_u16(take(2)),
+			height: BigEndian::read_u64(take(8)),
+			timestamp: BigEndian::read_i64(take(8)),
+			prev_hash: hash(take(32)),
+			prev_root: hash(take(32)),
+			output_root: hash(take(32)),
+			range_proof_root: hash(take(32)),
+			kernel_root: hash(take(32)),
+			total_kernel_offset: hash(take(32)),
+			output_mmr_size: BigEndian::read_u64(take(8)),
+			kernel_mmr_size: BigEndian::read_u64(take(8)),
+			total_difficulty: BigEndian::read_u64(take(8)),
+			secondary_scaling: BigEndian::read_u32(take(4)),
+		})
+	}
+
+	/// Serialize the pre-pow the way grin does
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = vec![0; PRE_POW_SIZE];
+		BigEndian::write_u16(&mut bytes[0..2], self.version);
+		BigEndian::write_u64(&mut bytes[2..10], self.height);
+		BigEndian::write_i64(&mut bytes[10..18], self.timestamp);
+		let hashes = [
+			&self.prev_hash,
+			&self.prev_root,
+			&self.output_root,
+			&self.range_proof_root,
+			&self.kernel_root,
+			&self.total_kernel_offset,
+		];
+		for (i, h) in hashes.iter().enumerate() {
+			bytes[18 + i * 32..18 + (i + 1) * 32].copy_from_slice(&h[..]);
+		}
+		BigEndian::write_u64(&mut bytes[210..218], self.output_mmr_size);
+		BigEndian::write_u64(&mut bytes[218..226], self.kernel_mmr_size);
+		BigEndian::write_u64(&mut bytes[226..234], self.total_difficulty);
+		BigEndian::write_u32(&mut bytes[234..238], self.secondary_scaling);
+		bytes
+	}
+
+	/// Hex string of the serialized pre-pow
+	pub fn to_hex(&self) -> String {
+		util::to_hex(self.to_bytes())
+	}
+}
+
+impl FromStr for PrePow {
+	type Err = HeaderError;
+
+	fn from_str(s: &str) -> Result<PrePow, HeaderError> {
+		PrePow::from_hex(s)
+	}
+}
+
+/// A header to solve, the pre-pow followed by the nonce
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockHeader {
+	/// Everything before the nonce
+	pub pre_pow: PrePow,
+	/// The nonce, extranonce included
+	pub nonce: u64,
+}
+
+impl BlockHeader {
+	/// Header for the given pre-pow and nonce
+	pub fn new(pre_pow: PrePow, nonce: u64) -> BlockHeader {
+		BlockHeader { pre_pow, nonce }
+	}
+
+	/// The bytes solvers hash to get the graph's siphash keys
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = self.pre_pow.to_bytes();
+		let mut nonce = [0; 8];
+		BigEndian::write_u64(&mut nonce, self.nonce);
+		bytes.extend_from_slice(&nonce);
+		bytes
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn pre_pow() -> PrePow {
+		PrePow {
+			version: 2,
+			height: 1_234_567,
+			timestamp: 1_600_000_000,
+			prev_hash: [1; 32],
+			prev_root: [2; 32],
+			output_root: [3; 32],
+			range_proof_root: [4; 32],
+			kernel_root: [5; 32],
+			total_kernel_offset: [6; 32],
+			output_mmr_size: 7,
+			kernel_mmr_size: 8,
+			total_difficulty: 9,
+			secondary_scaling: 10,
+		}
+	}
+
+	#[test]
+	fn pre_pow_round_trips() {
+		let p = pre_pow();
+		let hex = p.to_hex();
+		assert_eq!(hex.len(), PRE_POW_SIZE * 2);
+		assert!(hex.starts_with("0002000000000012d687000000005f5e1000"));
+		assert!(hex.ends_with("00000000000000090000000a"));
+		assert_eq!(PrePow::from_hex(&hex), Ok(p.clone()));
+		assert_eq!(hex.parse::<PrePow>(), Ok(p));
+	}
+
+	#[test]
+	fn header_puts_nonce_after_pre_pow() {
+		let header = BlockHeader::new(pre_pow(), 0x0102030405060708).to_bytes();
+		assert_eq!(header.len(), PRE_POW_SIZE + 8);
+		assert_eq!(&header[..PRE_POW_SIZE], &pre_pow().to_bytes()[..]);
+		assert_eq!(&header[PRE_POW_SIZE..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+	}
+
+	#[test]
+	fn rejects_bad_pre_pows() {
+		let hex = pre_pow().to_hex();
+		assert_eq!(
+			PrePow::from_hex(&hex[2..]),
+			Err(HeaderError::WrongSize(PRE_POW_SIZE - 1))
+		);
+		assert_eq!(PrePow::from_hex(&hex[1..]), Err(HeaderError::InvalidHex));
+		assert_eq!(
+			PrePow::from_hex(&hex.replacen("00", "zz", 1)),
+			Err(HeaderError::InvalidHex)
+		);
+		assert_eq!(PrePow::from_hex(""), Err(HeaderError::WrongSize(0)));
+	}
+}
@@ -17,17 +17,24 @@
 //! return any resulting solutions.
 
 use crate::util::LOGGER;
+use std::collections::VecDeque;
 use std::ptr::NonNull;
 use std::sync::{Arc, Mutex, RwLock, mpsc};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use std::{thread, time};
 
 use crate::config::types::PluginConfig;
-use crate::miner::types::{JobSharedData, JobSharedDataType, SolverInstance};
+use crate::miner::header::{BlockHeader, PrePow};
+use crate::miner::types::{
+	CurrentJob, Job, JobSharedData, JobSharedDataType, JobSolutions, MinerEvent, NonceMode,
+	RestartPolicy, SolverInstance, Subscribers, WatchdogPolicy,
+};
+use arc_swap::ArcSwap;
 
-use crate::miner::consensus::Proof;
+use crate::miner::consensus::{Proof, Variant};
 use crate::miner::util;
 use crate::{CuckooMinerError, PluginLibrary};
-use plugin::{Solution, SolverCtxWrapper, SolverSolutions, SolverStats};
+use plugin::{CuckooStopSolver, Solution, SolverCtxWrapper, SolverSolutions, SolverStats};
 
 /// Miner control Messages
 #[derive(Debug)]
@@ -38,10 +45,99 @@ enum ControlMessage {
 	Pause,
 	/// Resume
 	Resume,
+	/// Stop current mining iteration, and keep this solver paused
+	/// whatever the others do
+	PauseSolver,
+	/// Resume a solver paused on its own
+	ResumeSolver,
+	/// Mine only the given percentage of the time
+	SetDutyCycle(u8),
 	/// Solver reporting stopped
 	SolverStopped(usize),
 }
 
+/// Restarts are capped per this long
+const RESTART_WINDOW: time::Duration = time::Duration::from_secs(3600);
+
+/// How often a solver's control thread checks whether it's hung
+const WATCHDOG_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// A running solver's stop function and context
+type ActiveSolver = (Arc<Mutex<CuckooStopSolver>>, SolverCtxWrapper);
+
+/// How solver threads are set up to run
+#[derive(Clone, Copy, Default)]
+struct SolverSettings {
+	nonce_mode: NonceMode,
+	restart_policy: RestartPolicy,
+	watchdog_policy: WatchdogPolicy,
+}
+
+/// The ends of the channels a solver thread talks to the miner through
+struct SolverChannels {
+	control_rx: mpsc::Receiver<ControlMessage>,
+	solver_loop_rx: mpsc::Receiver<ControlMessage>,
+	solver_stopped_tx: mpsc::Sender<ControlMessage>,
+	solutions_tx: mpsc::Sender<JobSolutions>,
+	events: Subscribers,
+}
+
+/// What a solver thread has been told to do
+struct SolverState {
+	/// paused along with all other solvers
+	paused: bool,
+	/// paused on its own
+	solver_paused: bool,
+	/// percentage of the time to spend mining
+	duty_cycle: u8,
+}
+
+impl SolverState {
+	/// Apply a control message. Returns false if it's time to stop
+	fn update(&mut self, message: ControlMessage) -> bool {
+		match message {
+			ControlMessage::Stop => return false,
+			ControlMessage::Pause => self.paused = true,
+			ControlMessage::Resume => self.paused = false,
+			ControlMessage::PauseSolver => self.solver_paused = true,
+			ControlMessage::ResumeSolver => self.solver_paused = false,
+			ControlMessage::SetDutyCycle(duty_cycle) => self.duty_cycle = duty_cycle.clamp(1, 100),
+			ControlMessage::SolverStopped(_) => {}
+		}
+		true
+	}
+
+	fn mining(&self) -> bool {
+		!self.paused && !self.solver_paused
+	}
+
+	/// Wait for the given time, keeping up with control messages in the
+	/// meantime. Returns false if it's time to stop
+	fn wait(&mut self, rx: &mpsc::Receiver<ControlMessage>, duration: time::Duration) -> bool {
+		let until = Instant::now() + duration;
+		while let Some(left) = until.checked_duration_since(Instant::now()) {
+			match rx.recv_timeout(left) {
+				Ok(message) => {
+					if !self.update(message) {
+						return false;
+					}
+				}
+				Err(mpsc::RecvTimeoutError::Timeout) => break,
+				Err(mpsc::RecvTimeoutError::Disconnected) => return false,
+			}
+		}
+		true
+	}
+}
+
+/// The miner's ends of the channels to a solver thread
+struct SolverHandle {
+	id: usize,
+	control_tx: mpsc::Sender<ControlMessage>,
+	solver_loop_tx: mpsc::Sender<ControlMessage>,
+	solver_stopped_rx: mpsc::Receiver<ControlMessage>,
+}
+
 /// An instance of a miner, which loads a cuckoo-miner plugin
 /// and calls its mine function according to the provided configuration
 
@@ -52,14 +148,28 @@ pub struct CuckooMiner {
 	/// Data shared across threads
 	pub shared_data: Arc<RwLock<JobSharedData>>,
 
-	/// Job control tx
-	control_txs: Vec<mpsc::Sender<ControlMessage>>,
+	/// The job the solvers are working on
+	job: CurrentJob,
+
+	/// How solver threads are set up to run
+	settings: SolverSettings,
 
-	/// solver loop tx
-	solver_loop_txs: Vec<mpsc::Sender<ControlMessage>>,
+	/// Running solvers, in the order they were added
+	solvers: Vec<SolverHandle>,
 
-	/// Solver has stopped and cleanly shutdown
-	solver_stopped_rxs: Vec<mpsc::Receiver<ControlMessage>>,
+	/// Ids of removed solvers that didn't stop in time, which can't be
+	/// handed out again
+	abandoned_ids: Vec<usize>,
+
+	/// Whether solvers are paused
+	paused: bool,
+
+	/// Solutions from all solver threads, in the order they were found
+	solutions_tx: mpsc::Sender<JobSolutions>,
+	solutions_rx: mpsc::Receiver<JobSolutions>,
+
+	/// Subscribers to miner events
+	events: Subscribers,
 }
 
 impl CuckooMiner {
@@ -67,121 +177,195 @@ impl CuckooMiner {
 	/// One PluginConfig per device
 
 	pub fn new(configs: Vec<PluginConfig>) -> CuckooMiner {
-		let len = configs.len();
+		let (solutions_tx, solutions_rx) = mpsc::channel();
 		CuckooMiner {
 			configs,
-			shared_data: Arc::new(RwLock::new(JobSharedData::new(len))),
-			control_txs: vec![],
-			solver_loop_txs: vec![],
-			solver_stopped_rxs: vec![],
+			shared_data: Arc::new(RwLock::new(JobSharedData::default())),
+			job: Arc::new(ArcSwap::from_pointee(Job::default())),
+			settings: SolverSettings::default(),
+			solvers: vec![],
+			abandoned_ids: vec![],
+			paused: true,
+			solutions_tx,
+			solutions_rx,
+			events: Subscribers::default(),
 		}
 	}
 
-	/// Solver's instance of a thread
+	/// Solver's instance of a thread. Restarts the device according to
+	/// the restart policy whenever the plugin reports an error
 	fn solver_thread(
 		mut solver: SolverInstance,
-		instance: usize,
+		id: usize,
 		shared_data: JobSharedDataType,
-		control_rx: mpsc::Receiver<ControlMessage>,
-		solver_loop_rx: mpsc::Receiver<ControlMessage>,
-		solver_stopped_tx: mpsc::Sender<ControlMessage>,
+		current_job: CurrentJob,
+		settings: SolverSettings,
+		channels: SolverChannels,
 	) {
+		let SolverChannels {
+			control_rx,
+			solver_loop_rx,
+			solver_stopped_tx,
+			solutions_tx,
+			events,
+		} = channels;
 		{
 			let mut s = shared_data.write().unwrap();
-			s.stats[instance].set_plugin_name(&solver.config.name);
+			if let Some(i) = s.index_of(id) {
+				s.stats[i].set_plugin_name(&solver.config.name);
+			}
 		}
-		// "Detach" a stop function from the solver, to let us keep a control thread going
-		let ctx = solver.lib.create_solver_ctx(&mut solver.config.params);
-		let control_ctx = Arc::new(Mutex::new(SolverCtxWrapper(NonNull::new(ctx).unwrap())));
-
-		let stop_fn = solver.lib.get_stop_solver_instance();
+		// "Detach" a stop function from the solver, to let us keep a control
+		// thread going. It's swapped out whenever the device is restarted
+		let active: Arc<Mutex<Option<ActiveSolver>>> = Arc::new(Mutex::new(None));
+		let control_active = active.clone();
+		let control_shared_data = shared_data.clone();
+		let watchdog_policy = settings.watchdog_policy;
 
 		// monitor whether to send a stop signal to the solver, which should
-		// end the current solve attempt below
+		// end the current solve attempt below. Also stops it when it's hung
 		let stop_handle = thread::spawn(move || {
 			loop {
-				let ctx_ptr = control_ctx.lock().unwrap().0.as_ptr();
-				while let Some(message) = control_rx.iter().next() {
-					match message {
-						ControlMessage::Stop => {
-							PluginLibrary::stop_solver_from_instance(stop_fn.clone(), ctx_ptr);
-							return;
+				let stop = match control_rx.recv_timeout(WATCHDOG_INTERVAL) {
+					Ok(ControlMessage::Stop) | Err(mpsc::RecvTimeoutError::Disconnected) => true,
+					Ok(ControlMessage::Pause | ControlMessage::PauseSolver) => false,
+					Ok(_) => continue,
+					Err(mpsc::RecvTimeoutError::Timeout) => {
+						if !CuckooMiner::check_hung(&control_shared_data, id, &watchdog_policy) {
+							continue;
 						}
-						ControlMessage::Pause => {
-							PluginLibrary::stop_solver_from_instance(stop_fn.clone(), ctx_ptr);
-						}
-						_ => {}
-					};
+						false
+					}
+				};
+				// holding the lock keeps the context from being destroyed
+				// while we're stopping it
+				if let Some((stop_fn, ctx)) = control_active.lock().unwrap().as_ref() {
+					PluginLibrary::stop_solver_from_instance(stop_fn.clone(), ctx.0.as_ptr());
+				}
+				if stop {
+					return;
 				}
 			}
 		});
 
-		let mut iter_count = 0;
-		let mut paused = true;
-		loop {
-			if let Some(message) = solver_loop_rx.try_iter().next() {
-				debug!(
-					LOGGER,
-					"solver_thread - solver_loop_rx got msg: {:?}", message
-				);
-				match message {
-					ControlMessage::Stop => break,
-					ControlMessage::Pause => paused = true,
-					ControlMessage::Resume => paused = false,
-					_ => {}
-				}
-			}
-			if paused {
-				thread::sleep(time::Duration::from_micros(100));
-				continue;
-			}
-			{
-				let mut s = shared_data.write().unwrap();
-				s.stats[instance].set_plugin_name(&solver.config.name);
+		let mut walker = match settings.nonce_mode {
+			NonceMode::Random => None,
+			NonceMode::Partitioned { rig_id, range } => {
+				Some(util::NonceWalker::new(rig_id, id, range))
 			}
-			let header_pre = { shared_data.read().unwrap().pre_nonce.clone() };
-			let header_post = { shared_data.read().unwrap().post_nonce.clone() };
-			let height = { shared_data.read().unwrap().height };
-			let job_id = { shared_data.read().unwrap().job_id };
-			let target_difficulty = { shared_data.read().unwrap().difficulty };
-			let header = util::get_next_header_data(&header_pre, &header_post);
-			let nonce = header.0;
-			//let sec_scaling = header.2;
-			solver.lib.run_solver(
-				ctx,
-				header.1,
-				0,
-				1,
-				&mut solver.solutions,
-				&mut solver.stats,
+		};
+		let mut iter_count = 0;
+		let mut state = SolverState {
+			paused: true,
+			solver_paused: false,
+			duty_cycle: solver.config.duty_cycle.clamp(1, 100),
+		};
+		// when the device was restarted, over the last hour
+		let mut restarts: VecDeque<Instant> = VecDeque::new();
+		let mut restart_count = 0;
+		let mut invalid_proofs = 0;
+		let variant = plugin_variant(&solver.config);
+		if variant.is_none() {
+			warn!(
+				LOGGER,
+				"Can't tell which PoW plugin {} solves, its proofs won't be verified",
+				solver.config.name
 			);
-			iter_count += 1;
-			let still_valid = { height == shared_data.read().unwrap().height };
-			if still_valid {
-				let mut s = shared_data.write().unwrap();
-				s.stats[instance] = solver.stats.clone();
-				s.stats[instance].iterations = iter_count;
+		}
+		'device: loop {
+			let ctx = solver.lib.create_solver_ctx(&mut solver.config.params);
+			*active.lock().unwrap() = Some((
+				solver.lib.get_stop_solver_instance(),
+				SolverCtxWrapper(NonNull::new(ctx).unwrap()),
+			));
+			events.send(MinerEvent::SolverStarted { device: id });
+			let errored = loop {
+				if let Some(message) = solver_loop_rx.try_iter().next() {
+					debug!(
+						LOGGER,
+						"solver_thread - solver_loop_rx got msg: {:?}", message
+					);
+					if !state.update(message) {
+						break false;
+					}
+				}
+				if !state.mining() {
+					thread::sleep(time::Duration::from_micros(100));
+					continue;
+				}
+				let job = current_job.load_full();
+				let height = job.height;
+				let job_id = job.job_id;
+				let target_difficulty = job.difficulty;
+				let (nonce, range) = match walker.as_mut().and_then(|w| w.next(&job)) {
+					Some((nonce, range)) => (nonce, range),
+					// random mode. The extranonce is checked to leave room for a
+					// partition when it's set, so partitioned mode never ends up here
+					None => (util::random_nonce(&job.extranonce), 1),
+				};
+				let header = BlockHeader::new(job.pre_pow.clone(), nonce).to_bytes();
+				{
+					let mut s = shared_data.write().unwrap();
+					if let Some(i) = s.index_of(id) {
+						s.stats[i].set_plugin_name(&solver.config.name);
+						s.stats[i].last_start_time = now_nanos();
+						s.solving[i] = true;
+					}
+				}
+				let started = Instant::now();
+				solver.lib.run_solver(
+					ctx,
+					header,
+					nonce,
+					range,
+					&mut solver.solutions,
+					&mut solver.stats,
+				);
+				iter_count += 1;
+				let still_valid = height == current_job.load().height;
 				if solver.solutions.num_sols > 0 {
-					// Filter solutions that don't meet difficulty check
-					let mut filtered_sols: Vec<Solution> = vec![];
+					let mut found_sols: Vec<Solution> = vec![];
 					for i in 0..solver.solutions.num_sols {
-						filtered_sols.push(solver.solutions.sols[i as usize]);
+						let mut ss = solver.solutions.sols[i as usize];
+						// a solver covering more than one nonce reports which one
+						// each solution was found at
+						if range == 1 || ss.nonce < nonce || ss.nonce - nonce >= range as u64 {
+							ss.nonce = nonce;
+						}
+						ss.id = job_id as u64;
+						found_sols.push(ss);
 					}
-					let mut filtered_sols: Vec<Solution> = filtered_sols
-						.iter()
+					// Filter solutions that aren't valid proofs for the header, or
+					// that don't make a share at the pool's target
+					let edge_bits = solver.solutions.edge_bits as u8;
+					let filtered_sols: Vec<Solution> = found_sols
+						.into_iter()
 						.filter(|s| {
 							let proof = Proof {
-								edge_bits: solver.solutions.edge_bits as u8,
+								edge_bits,
 								nonces: s.proof.to_vec(),
 							};
-							proof.to_difficulty_unscaled().to_num() >= target_difficulty
+							if let Some(variant) = variant {
+								let header = BlockHeader::new(
+									job.pre_pow.clone(),
+									util::apply_extranonce(s.nonce, &job.extranonce),
+								)
+								.to_bytes();
+								if let Err(e) = proof.verify(&header, variant) {
+									warn!(
+										LOGGER,
+										"Plugin {} device {} found an invalid proof: {}",
+										solver.config.name,
+										id,
+										e
+									);
+									invalid_proofs += 1;
+									return false;
+								}
+							}
+							proof.meets_share_target(height, target_difficulty)
 						})
-						.cloned()
 						.collect();
-					for ss in filtered_sols.iter_mut() {
-						ss.nonce = nonce;
-						ss.id = job_id as u64;
-					}
 					solver.solutions.num_sols = filtered_sols.len() as u32;
 					for (i, _) in filtered_sols
 						.iter()
@@ -190,55 +374,250 @@ impl CuckooMiner {
 					{
 						solver.solutions.sols[i] = filtered_sols[i];
 					}
-					s.solutions.push(solver.solutions.clone());
+					// Pass on solutions even if the job has moved on since, it's up
+					// to the caller whether they're still worth submitting
+					for proof in filtered_sols.iter() {
+						events.send(MinerEvent::SolutionFound {
+							job: job.clone(),
+							device: id,
+							edge_bits: solver.solutions.edge_bits,
+							proof: Box::new(*proof),
+						});
+					}
+					if solver.solutions.num_sols > 0 {
+						let _ = solutions_tx.send(JobSolutions {
+							job_id,
+							height,
+							solutions: solver.solutions.clone(),
+						});
+					}
 				}
-				if s.stats[instance].has_errored {
-					s.stats[instance].set_plugin_name(&solver.config.name);
+				{
+					let mut s = shared_data.write().unwrap();
+					if let Some(i) = s.index_of(id) {
+						s.solving[i] = false;
+						s.stats[i].hung = false;
+						if still_valid || solver.stats.has_errored {
+							s.stats[i] = solver.stats.clone();
+							s.stats[i].set_plugin_name(&solver.config.name);
+							s.stats[i].iterations = iter_count;
+							s.stats[i].restarts = restart_count;
+							s.stats[i].invalid_proofs = invalid_proofs;
+							events.send(MinerEvent::StatsUpdated {
+								device: id,
+								stats: Box::new(s.stats[i].clone()),
+							});
+						}
+					}
+				}
+				if solver.stats.has_errored {
 					error!(
 						LOGGER,
 						"Plugin {} has errored, device: {}. Reason: {}",
-						s.stats[instance].get_plugin_name(),
-						s.stats[instance].get_device_name(),
-						s.stats[instance].get_error_reason(),
+						solver.config.name,
+						solver.stats.get_device_name(),
+						solver.stats.get_error_reason(),
+					);
+					events.send(MinerEvent::SolverErrored {
+						device: id,
+						reason: solver.stats.get_error_reason(),
+					});
+					break true;
+				}
+				solver.solutions = SolverSolutions::default();
+				// idle long enough to keep to the duty cycle
+				let idle =
+					started.elapsed() * (100 - state.duty_cycle as u32) / state.duty_cycle as u32;
+				if !state.wait(&solver_loop_rx, idle) {
+					break false;
+				}
+				thread::sleep(time::Duration::from_micros(100));
+			};
+
+			// tear the device down, and bring it back up if it errored
+			*active.lock().unwrap() = None;
+			solver.lib.destroy_solver_ctx(ctx);
+			solver.unload();
+			if !errored {
+				break;
+			}
+			let policy = settings.restart_policy;
+			if policy.max_per_hour == 0 {
+				break;
+			}
+			loop {
+				let now = Instant::now();
+				while restarts
+					.front()
+					.is_some_and(|t| now.duration_since(*t) >= RESTART_WINDOW)
+				{
+					restarts.pop_front();
+				}
+				let backoff = policy.delay.saturating_mul(1 << restarts.len().min(16));
+				let mut delay = backoff.min(policy.max_delay);
+				if restarts.len() >= policy.max_per_hour as usize {
+					// out of restarts until the oldest one is an hour old
+					let window_end = restarts[0] + RESTART_WINDOW;
+					delay = delay.max(window_end.duration_since(now));
+					warn!(
+						LOGGER,
+						"Device {} restarted {} times within the last hour, waiting {}s",
+						id,
+						restarts.len(),
+						delay.as_secs(),
 					);
-					break;
+				} else {
+					info!(LOGGER, "Restarting device {} in {}s", id, delay.as_secs());
+				}
+				if !state.wait(&solver_loop_rx, delay) {
+					break 'device;
+				}
+				restarts.push_back(Instant::now());
+				restart_count += 1;
+				{
+					let mut s = shared_data.write().unwrap();
+					if let Some(i) = s.index_of(id) {
+						s.stats[i].restarts = restart_count;
+					}
+				}
+				match SolverInstance::new(solver.config.clone()) {
+					Ok(s) => {
+						solver = s;
+						break;
+					}
+					Err(e) => error!(LOGGER, "Error restarting device {}: {:?}", id, e),
 				}
 			}
-			solver.solutions = SolverSolutions::default();
-			thread::sleep(time::Duration::from_micros(100));
 		}
 
 		let _ = stop_handle.join();
-		solver.lib.destroy_solver_ctx(ctx);
-		solver.unload();
-		let _ = solver_stopped_tx.send(ControlMessage::SolverStopped(instance));
+		events.send(MinerEvent::SolverStopped { device: id });
+		let _ = solver_stopped_tx.send(ControlMessage::SolverStopped(id));
+	}
+
+	/// Whether the given solver has been in a call to its plugin for
+	/// longer than the watchdog allows, flagging it as hung if so
+	fn check_hung(shared_data: &JobSharedDataType, id: usize, policy: &WatchdogPolicy) -> bool {
+		if policy.multiple <= 0.0 {
+			return false;
+		}
+		let mut s = shared_data.write().unwrap();
+		let Some(i) = s.index_of(id) else {
+			return false;
+		};
+		if !s.solving[i] || s.stats[i].hung {
+			return false;
+		}
+		let min_time = policy.min_time.as_nanos() as f64;
+		let limit = (s.stats[i].last_solution_time as f64 * policy.multiple).max(min_time);
+		let running = now_nanos().saturating_sub(s.stats[i].last_start_time) as f64;
+		if running <= limit {
+			return false;
+		}
+		s.stats[i].hung = true;
+		warn!(
+			LOGGER,
+			"Device {} ({}) has been solving for {:.1}s, trying to stop it",
+			id,
+			s.stats[i].get_device_name(),
+			running / 1_000_000_000.0,
+		);
+		true
 	}
 
 	/// Starts solvers, ready for jobs via job control
 	pub fn start_solvers(&mut self) -> Result<(), CuckooMinerError> {
-		let mut solvers = Vec::new();
 		for c in self.configs.clone() {
-			solvers.push(SolverInstance::new(c)?);
+			self.add_solver(c)?;
 		}
-		let mut i = 0;
-		for s in solvers {
-			let sd = self.shared_data.clone();
-			let (control_tx, control_rx) = mpsc::channel::<ControlMessage>();
-			let (solver_tx, solver_rx) = mpsc::channel::<ControlMessage>();
-			let (solver_stopped_tx, solver_stopped_rx) = mpsc::channel::<ControlMessage>();
-			self.control_txs.push(control_tx);
-			self.solver_loop_txs.push(solver_tx);
-			self.solver_stopped_rxs.push(solver_stopped_rx);
-			thread::spawn(move || {
-				CuckooMiner::solver_thread(s, i, sd, control_rx, solver_rx, solver_stopped_tx);
-			});
-			i += 1;
+		Ok(())
+	}
+
+	/// Starts a solver for another device, which joins the current job
+	/// straight away. Returns the id to remove it by
+	pub fn add_solver(&mut self, config: PluginConfig) -> Result<usize, CuckooMinerError> {
+		// the lowest free id, so ids double as device indexes
+		let id = (0..)
+			.find(|id| {
+				!self.solvers.iter().any(|s| s.id == *id) && !self.abandoned_ids.contains(id)
+			})
+			.unwrap();
+		if let NonceMode::Partitioned { .. } = self.settings.nonce_mode
+			&& id >= util::MAX_PARTITIONED_DEVICES
+		{
+			return Err(CuckooMinerError::ParameterError(format!(
+				"Can't partition nonces between more than {} devices",
+				util::MAX_PARTITIONED_DEVICES
+			)));
 		}
+		let solver = SolverInstance::new(config)?;
+		self.shared_data.write().unwrap().add(id);
+		let sd = self.shared_data.clone();
+		let job = self.job.clone();
+		let settings = self.settings;
+		let (control_tx, control_rx) = mpsc::channel::<ControlMessage>();
+		let (solver_tx, solver_rx) = mpsc::channel::<ControlMessage>();
+		let (solver_stopped_tx, solver_stopped_rx) = mpsc::channel::<ControlMessage>();
+		let channels = SolverChannels {
+			control_rx,
+			solver_loop_rx: solver_rx,
+			solver_stopped_tx,
+			solutions_tx: self.solutions_tx.clone(),
+			events: self.events.clone(),
+		};
+		thread::spawn(move || {
+			CuckooMiner::solver_thread(solver, id, sd, job, settings, channels);
+		});
+		if !self.paused {
+			let _ = solver_tx.send(ControlMessage::Resume);
+		}
+		self.solvers.push(SolverHandle {
+			id,
+			control_tx,
+			solver_loop_tx: solver_tx,
+			solver_stopped_rx,
+		});
+		Ok(id)
+	}
+
+	/// Stops the given solver and unloads its plugin, waiting for it to
+	/// stop for up to the shutdown timeout
+	pub fn remove_solver(&mut self, id: usize) -> Result<(), CuckooMinerError> {
+		self.solver(id)?;
+		let index = self.solvers.iter().position(|s| s.id == id).unwrap();
+		let solver = self.solvers.remove(index);
+		let _ = solver.control_tx.send(ControlMessage::Stop);
+		let _ = solver.solver_loop_tx.send(ControlMessage::Stop);
+		let timeout = self.settings.watchdog_policy.shutdown_timeout;
+		if let Err(mpsc::RecvTimeoutError::Timeout) = solver.solver_stopped_rx.recv_timeout(timeout)
+		{
+			warn!(LOGGER, "Solver {} didn't stop in time, leaving it", id);
+			self.abandoned_ids.push(id);
+		}
+		self.shared_data.write().unwrap().remove(id);
 		Ok(())
 	}
 
+	/// Choose how solvers pick their nonces. Takes effect for solvers
+	/// started after the call
+	pub fn set_nonce_mode(&mut self, nonce_mode: NonceMode) {
+		self.settings.nonce_mode = nonce_mode;
+	}
+
+	/// Choose when devices that report an error are restarted. Takes
+	/// effect for solvers started after the call
+	pub fn set_restart_policy(&mut self, restart_policy: RestartPolicy) {
+		self.settings.restart_policy = restart_policy;
+	}
+
+	/// Choose when solvers are considered hung. Takes effect for solvers
+	/// started after the call
+	pub fn set_watchdog_policy(&mut self, watchdog_policy: WatchdogPolicy) {
+		self.settings.watchdog_policy = watchdog_policy;
+	}
+
 	/// An asynchronous -esque version of the plugin miner, which takes
-	/// parts of the header and the target difficulty as input, and begins
+	/// the pre-pow of the header and the target difficulty as input, and begins
 	/// asyncronous processing to find a solution. The loaded plugin is
 	/// responsible
 	/// for how it wishes to manage processing or distribute the load. Once
@@ -249,53 +628,117 @@ impl CuckooMiner {
 
 	pub fn notify(
 		&mut self,
-		job_id: u32,      // Job id
-		height: u64,      // Job height
-		pre_nonce: &str,  // Pre-nonce portion of header
-		post_nonce: &str, // Post-nonce portion of header
-		difficulty: u64,  /* The target difficulty, only sols greater than this difficulty will
-		                   * be returned. */
+		job_id: u32,     // Job id
+		height: u64,     // Job height
+		pre_pow: PrePow, // Pre-nonce portion of header
+		difficulty: u64, /* The target difficulty, only sols greater than this difficulty will
+		                  * be returned. */
 	) -> Result<(), CuckooMinerError> {
-		let mut sd = self.shared_data.write().unwrap();
-		let paused = if height != sd.height {
+		let paused = if height != self.job.load().height {
 			// stop/pause any existing jobs if job is for a new
 			// height
 			self.pause_solvers();
 			true
 		} else {
-			false
+			self.paused
 		};
 
-		sd.job_id = job_id;
-		sd.height = height;
-		sd.pre_nonce = pre_nonce.to_owned();
-		sd.post_nonce = post_nonce.to_owned();
-		sd.difficulty = difficulty;
+		self.update_job(|job| {
+			job.job_id = job_id;
+			job.height = height;
+			job.pre_pow = pre_pow;
+			job.difficulty = difficulty;
+		});
 		if paused {
 			self.resume_solvers();
 		}
 		Ok(())
 	}
 
-	/// Returns solutions if currently waiting.
+	/// Change the target difficulty of the current job, e.g. when the
+	/// pool adjusts it without sending a new job
+	pub fn set_difficulty(&mut self, difficulty: u64) -> Result<(), CuckooMinerError> {
+		self.update_job(|job| job.difficulty = difficulty);
+		Ok(())
+	}
 
-	pub fn get_solutions(&self) -> Option<SolverSolutions> {
-		// just to prevent endless needless locking of this
-		// when using fast test miners, in real cuckoo30 terms
-		// this shouldn't be an issue
-		// TODO: Make this less blocky
-		// let time_pre_lock=Instant::now();
-		{
-			let mut s = self.shared_data.write().unwrap();
-			// let time_elapsed=Instant::now()-time_pre_lock;
-			// println!("Get_solution Time spent waiting for lock: {}",
-			// time_elapsed.as_secs()*1000 +(time_elapsed.subsec_nanos()/1_000_000)as u64);
-			if !s.solutions.is_empty() {
-				let sol = s.solutions.pop().unwrap();
-				return Some(sol);
+	/// Set the hex-encoded extranonce the pool assigned us, which all
+	/// nonces will start with from now on. An empty string clears it.
+	pub fn set_extranonce(&mut self, extranonce: &str) -> Result<(), CuckooMinerError> {
+		let bytes = util::from_hex_string(extranonce).unwrap_or_default();
+		let no_room = match self.settings.nonce_mode {
+			NonceMode::Partitioned { rig_id, .. } => {
+				util::nonce_partition(rig_id, 0, bytes.len()).is_none()
 			}
+			NonceMode::Random => false,
+		};
+		if bytes.len() * 2 != extranonce.len() || bytes.len() >= 8 || no_room {
+			return Err(CuckooMinerError::ParameterError(format!(
+				"Invalid extranonce: {}",
+				extranonce
+			)));
 		}
-		None
+		self.update_job(|job| job.extranonce = bytes);
+		Ok(())
+	}
+
+	/// Publish a new version of the current job with the given changes.
+	/// Only called with &mut self, so there's never more than one writer
+	fn update_job<F>(&self, f: F)
+	where
+		F: FnOnce(&mut Job),
+	{
+		let mut job = Job::clone(&self.job.load());
+		f(&mut job);
+		job.version += 1;
+		self.job.store(Arc::new(job));
+	}
+
+	/// The job the solvers are currently working on
+	pub fn current_job(&self) -> Arc<Job> {
+		self.job.load_full()
+	}
+
+	/// Returns all solutions waiting, oldest first, each tagged with the
+	/// job it was found for
+
+	pub fn get_solutions(&self) -> Vec<JobSolutions> {
+		self.solutions_rx.try_iter().collect()
+	}
+
+	/// Waits up to the given time for solutions to come in, returning as
+	/// soon as there are any, oldest first
+	pub fn wait_for_solutions(&self, timeout: time::Duration) -> Vec<JobSolutions> {
+		match self.solutions_rx.recv_timeout(timeout) {
+			Ok(first) => {
+				let mut solutions = vec![first];
+				solutions.extend(self.solutions_rx.try_iter());
+				solutions
+			}
+			Err(_) => vec![],
+		}
+	}
+
+	/// Hand the solutions channel over to someone else, leaving
+	/// get_solutions and wait_for_solutions with nothing to return
+	#[cfg(feature = "async")]
+	pub(crate) fn take_solutions(&mut self) -> mpsc::Receiver<JobSolutions> {
+		let (_, rx) = mpsc::channel();
+		std::mem::replace(&mut self.solutions_rx, rx)
+	}
+
+	/// Where solver threads send their solutions, for tests to stand in
+	/// for them
+	#[cfg(all(test, feature = "async"))]
+	pub(crate) fn solutions_sender(&self) -> mpsc::Sender<JobSolutions> {
+		self.solutions_tx.clone()
+	}
+
+	/// Listen for miner events, as an alternative to polling for solutions
+	/// and stats. Events are queued until received, so a subscriber
+	/// should keep up or drop the receiver
+	pub fn subscribe(&self) -> mpsc::Receiver<MinerEvent> {
+		self.events.subscribe()
 	}
 
 	/// get stats for all running solvers
@@ -314,46 +757,159 @@ impl CuckooMiner {
 	/// Nothing
 
 	pub fn stop_solvers(&self) {
-		for t in self.control_txs.iter() {
-			let _ = t.send(ControlMessage::Stop);
-		}
-		for t in self.solver_loop_txs.iter() {
-			let _ = t.send(ControlMessage::Stop);
+		for s in self.solvers.iter() {
+			let _ = s.control_tx.send(ControlMessage::Stop);
+			let _ = s.solver_loop_tx.send(ControlMessage::Stop);
 		}
 		debug!(LOGGER, "Stop message sent");
 	}
 
 	/// Tells current solvers to stop and wait
-	pub fn pause_solvers(&self) {
-		for t in self.control_txs.iter() {
-			let _ = t.send(ControlMessage::Pause);
-		}
-		for t in self.solver_loop_txs.iter() {
-			let _ = t.send(ControlMessage::Pause);
+	pub fn pause_solvers(&mut self) {
+		for s in self.solvers.iter() {
+			let _ = s.control_tx.send(ControlMessage::Pause);
+			let _ = s.solver_loop_tx.send(ControlMessage::Pause);
 		}
+		self.paused = true;
 		debug!(LOGGER, "Pause message sent");
 	}
 
 	/// Tells current solvers to stop and wait
-	pub fn resume_solvers(&self) {
-		for t in self.control_txs.iter() {
-			let _ = t.send(ControlMessage::Resume);
-		}
-		for t in self.solver_loop_txs.iter() {
-			let _ = t.send(ControlMessage::Resume);
+	pub fn resume_solvers(&mut self) {
+		for s in self.solvers.iter() {
+			let _ = s.control_tx.send(ControlMessage::Resume);
+			let _ = s.solver_loop_tx.send(ControlMessage::Resume);
 		}
+		self.paused = false;
 		debug!(LOGGER, "Resume message sent");
 	}
 
-	/// block until solvers have all exited
+	/// Stops the given solver's current attempt and keeps it paused,
+	/// whatever the others do, until resume_solver
+	pub fn pause_solver(&self, id: usize) -> Result<(), CuckooMinerError> {
+		let s = self.solver(id)?;
+		let _ = s.control_tx.send(ControlMessage::PauseSolver);
+		let _ = s.solver_loop_tx.send(ControlMessage::PauseSolver);
+		Ok(())
+	}
+
+	/// Lets a solver paused with pause_solver mine again
+	pub fn resume_solver(&self, id: usize) -> Result<(), CuckooMinerError> {
+		let s = self.solver(id)?;
+		let _ = s.solver_loop_tx.send(ControlMessage::ResumeSolver);
+		Ok(())
+	}
+
+	/// Has the given solver mine only the given percentage of the time,
+	/// idling in between attempts
+	pub fn set_duty_cycle(&self, id: usize, duty_cycle: u8) -> Result<(), CuckooMinerError> {
+		let s = self.solver(id)?;
+		let _ = s
+			.solver_loop_tx
+			.send(ControlMessage::SetDutyCycle(duty_cycle));
+		Ok(())
+	}
+
+	fn solver(&self, id: usize) -> Result<&SolverHandle, CuckooMinerError> {
+		self.solvers
+			.iter()
+			.find(|s| s.id == id)
+			.ok_or_else(|| CuckooMinerError::ParameterError(format!("No solver with id {}", id)))
+	}
+
+	/// block until solvers have all exited, or the shutdown timeout is
+	/// up. Solvers stuck in their plugin are left behind
 	pub fn wait_for_solver_shutdown(&self) {
-		for r in self.solver_stopped_rxs.iter() {
-			while let Some(message) = r.iter().next() {
-				if let ControlMessage::SolverStopped(i) = message {
-					debug!(LOGGER, "Solver stopped: {}", i);
-					break;
+		let deadline = Instant::now() + self.settings.watchdog_policy.shutdown_timeout;
+		for s in self.solvers.iter() {
+			let left = deadline.saturating_duration_since(Instant::now());
+			match s.solver_stopped_rx.recv_timeout(left) {
+				Ok(ControlMessage::SolverStopped(i)) => debug!(LOGGER, "Solver stopped: {}", i),
+				Ok(_) | Err(mpsc::RecvTimeoutError::Disconnected) => {}
+				Err(mpsc::RecvTimeoutError::Timeout) => {
+					warn!(LOGGER, "Solver {} didn't stop in time, leaving it", s.id)
 				}
 			}
 		}
 	}
 }
+
+/// Nanoseconds since the epoch, as solvers report times
+fn now_nanos() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_nanos() as u64)
+		.unwrap_or(0)
+}
+
+/// The PoW variant a plugin solves, by its name, or by its `variant` param
+/// for the Rust CPU plugin which handles more than one
+fn plugin_variant(config: &PluginConfig) -> Option<Variant> {
+	if config.name.starts_with("cpu_cuckoo") {
+		return Some(match config.params.variant {
+			1 => Variant::Cuckaroo,
+			_ => Variant::Cuckatoo,
+		});
+	}
+	Variant::from_plugin_name(&config.name)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	#[test]
+	fn job_reads_are_never_torn() {
+		let mut miner = CuckooMiner::new(vec![]);
+		let stop = Arc::new(AtomicBool::new(false));
+		// Read the job the way solver threads do, checking that every field
+		// belongs to the same job. Each field of job n is derived from n
+		let readers: Vec<_> = (0..4)
+			.map(|_| {
+				let current_job = miner.job.clone();
+				let stop = stop.clone();
+				thread::spawn(move || {
+					let mut last_version = 0;
+					let mut reads = 0;
+					while !stop.load(Ordering::Relaxed) {
+						let job = current_job.load_full();
+						// nothing published yet
+						if job.version == 0 {
+							continue;
+						}
+						let n = job.height;
+						assert_eq!(job.version, n);
+						assert_eq!(job.job_id as u64, n);
+						assert_eq!(job.difficulty, n);
+						assert_eq!(job.pre_pow.height, n);
+						assert_eq!(job.pre_pow.timestamp, n as i64);
+						assert!(job.version >= last_version);
+						last_version = job.version;
+						reads += 1;
+					}
+					reads
+				})
+			})
+			.collect();
+		for n in 1..20_000u64 {
+			miner
+				.notify(
+					n as u32,
+					n,
+					PrePow {
+						height: n,
+						timestamp: n as i64,
+						..Default::default()
+					},
+					n,
+				)
+				.unwrap();
+		}
+		stop.store(true, Ordering::Relaxed);
+		for reader in readers {
+			assert!(reader.join().unwrap() > 0);
+		}
+		assert_eq!(miner.current_job().version, 19_999);
+	}
+}
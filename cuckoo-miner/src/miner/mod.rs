@@ -21,7 +21,10 @@
 #![deny(unused_mut)]
 #![warn(missing_docs)]
 
+#[cfg(feature = "async")]
+pub mod async_miner;
 pub mod consensus;
+pub mod header;
 pub mod miner;
 pub mod types;
 pub mod util;
@@ -13,14 +13,20 @@
 // limitations under the License.
 
 //! Miner types
-use std::sync::{Arc, RwLock};
+use arc_swap::ArcSwap;
+use std::sync::{Arc, Mutex, RwLock, mpsc};
+use std::time::Duration;
 
 use crate::error::CuckooMinerError;
+use crate::miner::header::PrePow;
 use crate::{PluginConfig, PluginLibrary};
-use plugin::{SolverSolutions, SolverStats};
+use plugin::{Solution, SolverSolutions, SolverStats};
 
 pub type JobSharedDataType = Arc<RwLock<JobSharedData>>;
 
+/// The current job, swapped out as a whole when a new one comes in
+pub type CurrentJob = Arc<ArcSwap<Job>>;
+
 /// Holds a loaded lib + config + stats
 /// 1 instance = 1 device on 1 controlling thread
 pub struct SolverInstance {
@@ -52,56 +58,200 @@ impl SolverInstance {
 	}
 }
 
-/// Data intended to be shared across threads
-pub struct JobSharedData {
-	/// ID of the current running job (not currently used)
+/// Solutions from one solver run, tagged with the job they were found for
+#[derive(Clone)]
+pub struct JobSolutions {
+	/// ID of the job the solutions were found for
+	pub job_id: u32,
+	/// Height of that job
+	pub height: u64,
+	/// The solutions themselves
+	pub solutions: SolverSolutions,
+}
+
+/// A job for the solvers. Jobs are never changed once published, a
+/// changed job is published as a new snapshot with a higher version, so
+/// solvers always see all of one job and nothing of another
+#[derive(Debug, Clone, Default)]
+pub struct Job {
+	/// Increases by one with every job (or change to a job) published
+	pub version: u64,
+
+	/// ID of the job, as given by the pool
 	pub job_id: u32,
 
-	/// block height of current running job
+	/// block height of the job
 	pub height: u64,
 
-	/// The part of the header before the nonce, which this
-	/// module will mutate in search of a solution
-	pub pre_nonce: String,
+	/// The part of the header before the nonce, which solvers
+	/// follow with the nonces they try
+	pub pre_pow: PrePow,
 
-	/// The part of the header after the nonce
-	pub post_nonce: String,
+	/// Bytes every nonce has to start with, as assigned by the pool
+	pub extranonce: Vec<u8>,
 
 	/// The target difficulty. Only solutions >= this
 	/// target will be put into the output queue
 	pub difficulty: u64,
+}
 
-	/// Output solutions
-	pub solutions: Vec<SolverSolutions>,
+/// How solver threads pick the nonces they try
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum NonceMode {
+	/// A fresh random nonce for every attempt
+	#[default]
+	Random,
+	/// Each device walks its own slice of the nonce space, derived from
+	/// the rig id and its index, asking the solver for `range` nonces at
+	/// a time
+	Partitioned {
+		/// Id of this rig, unique among rigs sharing a login
+		rig_id: u16,
+		/// Nonces to hand the solver per attempt
+		range: u32,
+	},
+}
 
-	/// Current stats
-	pub stats: Vec<SolverStats>,
+/// Something that happened in the miner, as sent to subscribers.
+/// Devices are identified by their solver id
+#[derive(Clone)]
+pub enum MinerEvent {
+	/// A solver is ready to mine, after starting or restarting
+	SolverStarted {
+		/// solver id
+		device: usize,
+	},
+	/// A solver found a solution meeting the job's difficulty
+	SolutionFound {
+		/// the job the solution was found for
+		job: Arc<Job>,
+		/// solver id
+		device: usize,
+		/// graph size the solution was found at
+		edge_bits: u32,
+		/// nonce and cycle of the solution
+		proof: Box<Solution>,
+	},
+	/// A solver finished an attempt, with its stats as of then
+	StatsUpdated {
+		/// solver id
+		device: usize,
+		/// the solver's stats
+		stats: Box<SolverStats>,
+	},
+	/// A solver's plugin reported an error
+	SolverErrored {
+		/// solver id
+		device: usize,
+		/// the reason the plugin gave
+		reason: String,
+	},
+	/// A solver thread has exited
+	SolverStopped {
+		/// solver id
+		device: usize,
+	},
+}
+
+/// Everyone listening for miner events
+#[derive(Clone, Default)]
+pub struct Subscribers(Arc<Mutex<Vec<mpsc::Sender<MinerEvent>>>>);
+
+impl Subscribers {
+	/// Add a subscriber, which gets all events from now on
+	pub fn subscribe(&self) -> mpsc::Receiver<MinerEvent> {
+		let (tx, rx) = mpsc::channel();
+		self.0.lock().unwrap().push(tx);
+		rx
+	}
+
+	/// Send an event to every subscriber, forgetting those that have
+	/// dropped their receiver
+	pub fn send(&self, event: MinerEvent) {
+		self.0
+			.lock()
+			.unwrap()
+			.retain(|tx| tx.send(event.clone()).is_ok());
+	}
 }
 
-impl Default for JobSharedData {
-	fn default() -> JobSharedData {
-		JobSharedData {
-			job_id: 0,
-			height: 0,
-			pre_nonce: String::from(""),
-			post_nonce: String::from(""),
-			difficulty: 0,
-			solutions: Vec::new(),
-			stats: vec![],
+/// When to bring a device that reported an error back up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RestartPolicy {
+	/// Wait before restarting, doubled for every restart of the device
+	/// within the last hour
+	pub delay: Duration,
+	/// Longest wait before restarting
+	pub max_delay: Duration,
+	/// Most restarts of a device within an hour, 0 to leave errored
+	/// devices stopped
+	pub max_per_hour: u32,
+}
+
+impl Default for RestartPolicy {
+	fn default() -> RestartPolicy {
+		RestartPolicy {
+			delay: Duration::from_secs(5),
+			max_delay: Duration::from_secs(300),
+			max_per_hour: 5,
 		}
 	}
 }
 
+/// When to consider a device stuck in a call into its plugin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchdogPolicy {
+	/// A solve taking this many times the device's last graph time is
+	/// hung, 0 to never check
+	pub multiple: f64,
+	/// Shortest time a solve may take before it's considered hung, which
+	/// also covers the first solve of a device
+	pub min_time: Duration,
+	/// How long to wait for solvers to stop at shutdown before giving up
+	/// on them
+	pub shutdown_timeout: Duration,
+}
+
+impl Default for WatchdogPolicy {
+	fn default() -> WatchdogPolicy {
+		WatchdogPolicy {
+			multiple: 10.0,
+			min_time: Duration::from_secs(60),
+			shutdown_timeout: Duration::from_secs(10),
+		}
+	}
+}
+
+/// Data intended to be shared across threads
+#[derive(Default)]
+pub struct JobSharedData {
+	/// Current stats, one entry per solver
+	pub stats: Vec<SolverStats>,
+	/// Whether each solver is in a call to its plugin's run_solver
+	pub solving: Vec<bool>,
+	/// Id of the solver the entries at each index belong to
+	pub ids: Vec<usize>,
+}
+
 impl JobSharedData {
-	pub fn new(num_solvers: usize) -> JobSharedData {
-		JobSharedData {
-			job_id: 0,
-			height: 0,
-			pre_nonce: String::from(""),
-			post_nonce: String::from(""),
-			difficulty: 1,
-			solutions: Vec::new(),
-			stats: vec![SolverStats::default(); num_solvers],
+	/// Index of the given solver's entries, unless it's been removed
+	pub fn index_of(&self, id: usize) -> Option<usize> {
+		self.ids.iter().position(|i| *i == id)
+	}
+
+	/// Add entries for a new solver
+	pub fn add(&mut self, id: usize) {
+		self.stats.push(SolverStats::default());
+		self.solving.push(false);
+		self.ids.push(id);
+	}
+
+	/// Drop the entries of a removed solver
+	pub fn remove(&mut self, id: usize) {
+		if let Some(i) = self.index_of(id) {
+			self.stats.remove(i);
+			self.solving.remove(i);
+			self.ids.remove(i);
 		}
 	}
 }
@@ -17,40 +17,163 @@
 use byteorder::{BigEndian, ByteOrder};
 use rand::{self, TryRngCore};
 
-pub fn header_data(pre_nonce: &str, post_nonce: &str, nonce: u64) -> (Vec<u8>, u32) {
-	// Turn input strings into vectors
-	let mut pre_vec = from_hex_string(pre_nonce);
-	let mut post_vec = from_hex_string(post_nonce);
+use crate::miner::types::Job;
 
-	let sec_scaling_bytes = &pre_vec.clone()[pre_vec.len() - 4..pre_vec.len()];
-	let sec_scaling = BigEndian::read_u32(&sec_scaling_bytes);
+/// Bits of a partitioned nonce holding the rig id
+const RIG_ID_BITS: u32 = 16;
+/// Bits of a partitioned nonce holding the device index
+const DEVICE_BITS: u32 = 8;
 
+/// Most devices a rig can partition the nonce space between
+pub const MAX_PARTITIONED_DEVICES: usize = 1 << DEVICE_BITS;
+
+/// Overwrite the leading bytes of a nonce with the extranonce the pool
+/// assigned us, so our nonces don't overlap with other miners'
+pub fn apply_extranonce(nonce: u64, extranonce: &[u8]) -> u64 {
 	let mut nonce_bytes = [0; 8];
 	BigEndian::write_u64(&mut nonce_bytes, nonce);
-	let mut nonce_vec = nonce_bytes.to_vec();
+	let len = extranonce.len().min(8);
+	nonce_bytes[..len].copy_from_slice(&extranonce[..len]);
+	BigEndian::read_u64(&nonce_bytes)
+}
+
+/// A random nonce starting with the extranonce
+pub fn random_nonce(extranonce: &[u8]) -> u64 {
+	apply_extranonce(rand::rngs::OsRng.try_next_u64().unwrap(), extranonce)
+}
+
+/// The slice of the nonce space a device walks in partitioned mode, as
+/// its first nonce and its size. The rig id and device index come right
+/// after the extranonce, so no two devices of any two rigs with different
+/// ids get overlapping slices. None if the extranonce leaves no room.
+pub fn nonce_partition(rig_id: u16, device: usize, extranonce_len: usize) -> Option<(u64, u64)> {
+	if device >= MAX_PARTITIONED_DEVICES {
+		return None;
+	}
+	let free_bits = 64u32.checked_sub(8 * extranonce_len as u32)?;
+	let walk_bits = free_bits.checked_sub(RIG_ID_BITS + DEVICE_BITS)?;
+	if walk_bits == 0 {
+		return None;
+	}
+	let prefix = ((rig_id as u64) << DEVICE_BITS) | device as u64;
+	Some((prefix << walk_bits, 1 << walk_bits))
+}
+
+/// Walks a device's nonce partition in order, starting over at the
+/// beginning whenever the job's header changes
+pub struct NonceWalker {
+	rig_id: u16,
+	device: usize,
+	range: u32,
+	/// job id, height and extranonce of the job being walked
+	job: Option<(u32, u64, Vec<u8>)>,
+	walked: u64,
+}
 
-	// Generate new header
-	pre_vec.append(&mut nonce_vec);
-	pre_vec.append(&mut post_vec);
+impl NonceWalker {
+	/// Walker for the given device of the rig, handing out `range`
+	/// nonces at a time
+	pub fn new(rig_id: u16, device: usize, range: u32) -> NonceWalker {
+		NonceWalker {
+			rig_id,
+			device,
+			range: range.max(1),
+			job: None,
+			walked: 0,
+		}
+	}
 
-	(pre_vec, sec_scaling)
+	/// The next nonce to try for the job, and how many nonces from it
+	/// on the solver should cover. Wraps around at the end of the partition
+	pub fn next(&mut self, job: &Job) -> Option<(u64, u32)> {
+		let (start, size) = nonce_partition(self.rig_id, self.device, job.extranonce.len())?;
+		let key = (job.job_id, job.height, job.extranonce.clone());
+		if self.job.as_ref() != Some(&key) {
+			self.job = Some(key);
+			self.walked = 0;
+		}
+		let offset = self.walked % size;
+		let range = (self.range as u64).min(size - offset);
+		self.walked = self.walked.wrapping_add(range);
+		Some((
+			apply_extranonce(start + offset, &job.extranonce),
+			range as u32,
+		))
+	}
 }
 
-pub fn get_next_header_data(pre_nonce: &str, post_nonce: &str) -> (u64, Vec<u8>, u32) {
-	let nonce: u64 = rand::rngs::OsRng.try_next_u64().unwrap();
-	let (hd, sec_scaling) = header_data(pre_nonce, post_nonce, nonce);
-	(nonce, hd, sec_scaling)
+/// Helper to convert a hex string, `None` if it isn't one
+pub fn from_hex_string(in_str: &str) -> Option<Vec<u8>> {
+	if !in_str.len().is_multiple_of(2) {
+		return None;
+	}
+	(0..in_str.len() / 2)
+		.map(|i| u8::from_str_radix(in_str.get(2 * i..2 * i + 2)?, 16).ok())
+		.collect()
 }
 
-/// Helper to convert a hex string
-pub fn from_hex_string(in_str: &str) -> Vec<u8> {
-	let mut bytes = Vec::new();
-	for i in 0..(in_str.len() / 2) {
-		let res = u8::from_str_radix(&in_str[2 * i..2 * i + 2], 16);
-		match res {
-			Ok(v) => bytes.push(v),
-			Err(e) => println!("Problem with hex: {}", e),
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn job(job_id: u32, extranonce: &[u8]) -> Job {
+		Job {
+			job_id,
+			extranonce: extranonce.to_vec(),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn partitions_are_disjoint() {
+		let mut partitions = vec![];
+		for rig_id in [0, 1, 7, u16::MAX] {
+			for device in [0, 1, 2, MAX_PARTITIONED_DEVICES - 1] {
+				partitions.push(nonce_partition(rig_id, device, 2).unwrap());
+			}
+		}
+		for (i, (start_a, size_a)) in partitions.iter().enumerate() {
+			assert_eq!(*size_a, 1 << 24);
+			for (start_b, size_b) in partitions.iter().skip(i + 1) {
+				assert!(start_a + size_a <= *start_b || start_b + size_b <= *start_a);
+			}
 		}
+		// the extranonce bytes are left alone
+		let (start, size) = nonce_partition(u16::MAX, MAX_PARTITIONED_DEVICES - 1, 2).unwrap();
+		assert!((start + size - 1) >> 48 == 0);
+	}
+
+	#[test]
+	fn no_partition_without_room() {
+		assert!(nonce_partition(0, 0, 4).is_some());
+		assert!(nonce_partition(0, 0, 5).is_none());
+		assert!(nonce_partition(0, 0, 8).is_none());
+		assert!(nonce_partition(0, MAX_PARTITIONED_DEVICES, 0).is_none());
+		let mut walker = NonceWalker::new(0, 0, 1);
+		assert_eq!(walker.next(&job(1, &[1, 2, 3, 4, 5])), None);
+	}
+
+	#[test]
+	fn walker_wraps_around_and_resets_on_new_jobs() {
+		let extranonce = [0xaa, 0xbb, 0xcc, 0xdd];
+		// leaves a partition of 256 nonces
+		let (start, size) = nonce_partition(3, 1, extranonce.len()).unwrap();
+		assert_eq!(size, 256);
+		let start = apply_extranonce(start, &extranonce);
+		let mut walker = NonceWalker::new(3, 1, 100);
+		let first = job(1, &extranonce);
+		assert_eq!(walker.next(&first), Some((start, 100)));
+		assert_eq!(walker.next(&first), Some((start + 100, 100)));
+		assert_eq!(walker.next(&first), Some((start + 200, 56)));
+		assert_eq!(walker.next(&first), Some((start, 100)));
+		assert_eq!(walker.next(&first), Some((start + 100, 100)));
+		// a new job starts over
+		assert_eq!(walker.next(&job(2, &extranonce)), Some((start, 100)));
+		// and so does a new extranonce
+		let other = apply_extranonce(start, &[0x11, 0x22, 0x33, 0x44]);
+		assert_eq!(
+			walker.next(&job(2, &[0x11, 0x22, 0x33, 0x44])),
+			Some((other, 100))
+		);
 	}
-	bytes
 }
@@ -0,0 +1,15 @@
+plugins_dir=$(egrep '^miner_plugin_dir' grin-miner.toml | awk '{ print $NF }' | xargs echo)
+if [ -z "$plugins_dir" ]; then
+	plugins_dir="target/release/plugins"
+fi
+mkdir -p "$plugins_dir";
+
+# Install cpu_cuckoo
+cd cpu_cuckoo
+cargo build --release
+cd ..
+if [ "$(uname)" = "Darwin" ]; then
+	cp target/release/libcpu_cuckoo.dylib $plugins_dir/cpu_cuckoo.cuckooplugin
+else
+	cp target/release/libcpu_cuckoo.so $plugins_dir/cpu_cuckoo.cuckooplugin
+fi
@@ -116,6 +116,8 @@ pub struct SolverParams {
 	pub platform: u32,
 	/// edge bits for OCL plugins
 	pub edge_bits: u32,
+	/// Graph variant for the Rust CPU plugin, 0 - Cuckatoo, 1 - Cuckaroo
+	pub variant: u32,
 }
 
 impl Default for SolverParams {
@@ -140,6 +142,7 @@ impl Default for SolverParams {
 			recovertpb: 0,
 			platform: 0,
 			edge_bits: 31,
+			variant: 0,
 		}
 	}
 }
@@ -168,6 +171,12 @@ pub struct SolverStats {
 	pub last_end_time: u64,
 	/// last solution elapsed time
 	pub last_solution_time: u64,
+	/// number of times the miner restarted the device after an error
+	pub restarts: u32,
+	/// whether the miner's watchdog found the device stuck in a solve
+	pub hung: bool,
+	/// number of solutions the miner threw away as invalid proofs
+	pub invalid_proofs: u32,
 }
 
 impl Default for SolverStats {
@@ -183,6 +192,9 @@ impl Default for SolverStats {
 			last_start_time: 0,
 			last_end_time: 0,
 			last_solution_time: 0,
+			restarts: 0,
+			hung: false,
+			invalid_proofs: 0,
 		}
 	}
 }
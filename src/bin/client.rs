@@ -15,25 +15,40 @@
 //! Client network controller, controls requests and responses from the
 //! stratum server
 
+use crate::config;
+use crate::proxy::Proxy;
 use crate::stats;
 use crate::types;
-use bufstream::BufStream;
-use native_tls::{TlsConnector, TlsStream};
+use cuckoo::PrePow;
+use mio::{Events, Interest, Poll, Token, Waker};
+use native_tls::{Certificate, HandshakeError, Identity, TlsConnector, TlsStream};
 use serde_json;
 use std;
-use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
 use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock, mpsc};
 use std::thread;
+use std::time::{Duration, Instant};
 use time;
 use util::LOGGER;
 
 #[derive(Debug)]
 pub enum Error {
 	ConnectionError(String),
+	ProxyError(String),
+	TlsServerNameError(String),
+	TlsCaError(String),
+	TlsClientCertError(String),
+	TlsConnectorError(String),
+	TlsHandshakeError(String),
 	RequestError(String),
 	ResponseError(String),
 	JsonError(String),
+	InvalidJobError(String),
 	GeneralError(String),
 }
 
@@ -55,9 +70,18 @@ impl<T> From<std::sync::mpsc::SendError<T>> for Error {
 	}
 }
 
+/// Poll token for the server socket
+const STREAM_TOKEN: Token = Token(0);
+/// Poll token used to wake the connection thread when there's something to send
+const WAKER_TOKEN: Token = Token(1);
+/// How long to wait for the server during a TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to keep trying to send queued messages when closing a connection
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
 struct Stream {
-	stream: Option<BufStream<TcpStream>>,
-	tls_stream: Option<BufStream<TlsStream<TcpStream>>>,
+	stream: Option<mio::net::TcpStream>,
+	tls_stream: Option<TlsStream<mio::net::TcpStream>>,
 }
 
 impl Stream {
@@ -67,36 +91,61 @@ impl Stream {
 			tls_stream: None,
 		}
 	}
-	fn try_connect(&mut self, server_url: &str, tls: Option<bool>) -> Result<(), Error> {
-		match TcpStream::connect(server_url) {
-			Ok(conn) => {
-				if tls.is_some() && tls.unwrap() {
-					let connector = TlsConnector::new().map_err(|e| {
-						Error::ConnectionError(format!("Can't create TLS connector: {:?}", e))
-					})?;
-					let url_port: Vec<&str> = server_url.split(':').collect();
-					let splitted_url: Vec<&str> = url_port[0].split('.').collect();
-					let base_host = format!(
-						"{}.{}",
-						splitted_url[splitted_url.len() - 2],
-						splitted_url[splitted_url.len() - 1]
-					);
-					let mut stream = connector.connect(&base_host, conn).map_err(|e| {
-						Error::ConnectionError(format!("Can't establish TLS connection: {:?}", e))
-					})?;
-					stream.get_mut().set_nonblocking(true).map_err(|e| {
-						Error::ConnectionError(format!("Can't switch to nonblocking mode: {:?}", e))
-					})?;
-					self.tls_stream = Some(BufStream::new(stream));
-				} else {
-					conn.set_nonblocking(true).map_err(|e| {
-						Error::ConnectionError(format!("Can't switch to nonblocking mode: {:?}", e))
-					})?;
-					self.stream = Some(BufStream::new(conn));
-				}
-				Ok(())
+
+	/// Connects to the given server, through the proxy if there is one,
+	/// returning the poll instance the resulting socket is registered with
+	fn try_connect(
+		&mut self,
+		server: &config::StratumServerConfig,
+		proxy: Option<&Proxy>,
+	) -> Result<Poll, Error> {
+		let conn = match proxy {
+			Some(proxy) => proxy
+				.connect(&server.addr)
+				.map_err(|e| Error::ProxyError(format!("{}", e)))?,
+			None => TcpStream::connect(&server.addr)
+				.map_err(|e| Error::ConnectionError(format!("{}", e)))?,
+		};
+		let _ = conn.set_nodelay(true);
+		conn.set_nonblocking(true).map_err(|e| {
+			Error::ConnectionError(format!("Can't switch to nonblocking mode: {:?}", e))
+		})?;
+		let mut conn = mio::net::TcpStream::from_std(conn);
+		let mut poll =
+			Poll::new().map_err(|e| Error::ConnectionError(format!("Can't create poll: {}", e)))?;
+		poll.registry()
+			.register(
+				&mut conn,
+				STREAM_TOKEN,
+				Interest::READABLE | Interest::WRITABLE,
+			)
+			.map_err(|e| Error::ConnectionError(format!("Can't register socket: {}", e)))?;
+		if server.tls_enabled == Some(true) {
+			let connector = tls_connector(server)?;
+			let server_name = match server.tls_server_name.as_ref() {
+				Some(name) => name.clone(),
+				None => address_host(&server.addr).to_owned(),
+			};
+			if server_name.is_empty() {
+				return Err(Error::TlsServerNameError(format!(
+					"Can't determine TLS server name for {}",
+					server.addr
+				)));
 			}
-			Err(e) => Err(Error::ConnectionError(format!("{}", e))),
+			let stream = tls_handshake(&mut poll, &connector, &server_name, conn)?;
+			self.tls_stream = Some(stream);
+		} else {
+			self.stream = Some(conn);
+		}
+		Ok(poll)
+	}
+
+	/// Let the server know we're done writing
+	fn shutdown(&mut self) {
+		if let Some(tls_stream) = self.tls_stream.as_mut() {
+			let _ = tls_stream.shutdown();
+		} else if let Some(stream) = self.stream.as_ref() {
+			let _ = stream.shutdown(std::net::Shutdown::Write);
 		}
 	}
 }
@@ -127,44 +176,373 @@ impl Read for Stream {
 	}
 }
 
-impl BufRead for Stream {
-	fn fill_buf(&mut self) -> io::Result<&[u8]> {
-		if self.tls_stream.is_some() {
-			self.tls_stream.as_mut().unwrap().fill_buf()
-		} else {
-			self.stream.as_mut().unwrap().fill_buf()
+/// Drives a TLS handshake over a nonblocking socket to completion
+fn tls_handshake(
+	poll: &mut Poll,
+	connector: &TlsConnector,
+	server_name: &str,
+	conn: mio::net::TcpStream,
+) -> Result<TlsStream<mio::net::TcpStream>, Error> {
+	let handshake_error = |e: String| {
+		Error::TlsHandshakeError(format!(
+			"Can't establish TLS connection to {}: {}",
+			server_name, e
+		))
+	};
+	let mut events = Events::with_capacity(16);
+	let mut result = connector.connect(server_name, conn);
+	loop {
+		match result {
+			Ok(stream) => return Ok(stream),
+			Err(HandshakeError::WouldBlock(mid)) => {
+				poll.poll(&mut events, Some(TLS_HANDSHAKE_TIMEOUT))
+					.map_err(|e| handshake_error(format!("{}", e)))?;
+				if events.is_empty() {
+					return Err(handshake_error("timed out".to_owned()));
+				}
+				result = mid.handshake();
+			}
+			Err(HandshakeError::Failure(e)) => return Err(handshake_error(format!("{:?}", e))),
 		}
 	}
-	fn consume(&mut self, amt: usize) {
-		if self.tls_stream.is_some() {
-			self.tls_stream.as_mut().unwrap().consume(amt)
-		} else {
-			self.stream.as_mut().unwrap().consume(amt)
+}
+
+/// An established server connection, serviced by its own thread which
+/// passes each line received on to the client controller as soon as it
+/// arrives, and writes out anything sent through the handle straight away
+struct Connection {
+	id: u64,
+	poll: Poll,
+	stream: Stream,
+}
+
+impl Connection {
+	fn open(
+		id: u64,
+		server: &config::StratumServerConfig,
+		proxy: Option<&Proxy>,
+	) -> Result<Connection, Error> {
+		let mut stream = Stream::new();
+		let poll = stream.try_connect(server, proxy)?;
+		Ok(Connection { id, poll, stream })
+	}
+
+	/// Start the connection thread
+	fn spawn(
+		self,
+		client_tx: mpsc::Sender<types::ClientMessage>,
+	) -> Result<ConnectionHandle, Error> {
+		let waker = Waker::new(self.poll.registry(), WAKER_TOKEN)
+			.map_err(|e| Error::ConnectionError(format!("Can't create waker: {}", e)))?;
+		let (tx, rx) = mpsc::channel::<String>();
+		let id = self.id;
+		let closed = Arc::new(AtomicBool::new(false));
+		let thread_closed = closed.clone();
+		let thread = thread::Builder::new()
+			.name("stratum_connection".to_string())
+			.spawn(move || self.run(rx, thread_closed, client_tx))
+			.map_err(|e| Error::ConnectionError(format!("Can't start connection thread: {}", e)))?;
+		Ok(ConnectionHandle {
+			id,
+			tx,
+			waker: Arc::new(waker),
+			closed,
+			thread: Some(thread),
+		})
+	}
+
+	fn run(
+		mut self,
+		rx: mpsc::Receiver<String>,
+		closed: Arc<AtomicBool>,
+		client_tx: mpsc::Sender<types::ClientMessage>,
+	) {
+		let mut events = Events::with_capacity(64);
+		let mut read_buf: Vec<u8> = vec![];
+		let mut write_buf: Vec<u8> = vec![];
+		loop {
+			if let Err(e) = self.poll.poll(&mut events, None) {
+				if e.kind() == ErrorKind::Interrupted {
+					continue;
+				}
+				error!(LOGGER, "Error polling stratum connection: {}", e);
+				break;
+			}
+			for message in rx.try_iter() {
+				write_buf.extend_from_slice(message.as_bytes());
+				write_buf.push(b'\n');
+			}
+			if closed.load(Ordering::Relaxed) {
+				self.close(&mut write_buf);
+				return;
+			}
+			// Events are edge triggered, so always drain the socket in
+			// both directions whatever woke us up
+			if let Err(e) = self.write_pending(&mut write_buf) {
+				error!(LOGGER, "Communication error with stratum server: {}", e);
+				break;
+			}
+			match self.read_lines(&mut read_buf, &client_tx) {
+				Ok(true) => {}
+				Ok(false) => {
+					debug!(LOGGER, "Stratum server closed the connection");
+					break;
+				}
+				Err(e) => {
+					error!(LOGGER, "Communication error with stratum server: {}", e);
+					break;
+				}
+			}
 		}
+		let _ = client_tx.send(types::ClientMessage::ServerDisconnected(self.id));
 	}
-	fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>) -> io::Result<usize> {
-		if self.tls_stream.is_some() {
-			self.tls_stream.as_mut().unwrap().read_until(byte, buf)
-		} else {
-			self.stream.as_mut().unwrap().read_until(byte, buf)
+
+	/// Give whatever is still queued a last chance to go out, then close
+	fn close(&mut self, write_buf: &mut Vec<u8>) {
+		let deadline = Instant::now() + CLOSE_TIMEOUT;
+		let mut events = Events::with_capacity(16);
+		while self.write_pending(write_buf).is_ok() && !write_buf.is_empty() {
+			let now = Instant::now();
+			if now >= deadline {
+				break;
+			}
+			let _ = self.poll.poll(&mut events, Some(deadline - now));
 		}
+		self.stream.shutdown();
 	}
-	fn read_line(&mut self, string: &mut String) -> io::Result<usize> {
-		if self.tls_stream.is_some() {
-			self.tls_stream.as_mut().unwrap().read_line(string)
-		} else {
-			self.stream.as_mut().unwrap().read_line(string)
+
+	/// Write as much of the buffer as the socket will take
+	fn write_pending(&mut self, write_buf: &mut Vec<u8>) -> io::Result<()> {
+		while !write_buf.is_empty() {
+			match self.stream.write(write_buf) {
+				Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero)),
+				Ok(n) => {
+					write_buf.drain(..n);
+				}
+				Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+				Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+				Err(e) => return Err(e),
+			}
+		}
+		match self.stream.flush() {
+			Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+			r => r,
+		}
+	}
+
+	/// Read everything available, handing each complete line to the client
+	/// controller. Returns false once the server has closed the connection
+	fn read_lines(
+		&mut self,
+		read_buf: &mut Vec<u8>,
+		client_tx: &mpsc::Sender<types::ClientMessage>,
+	) -> io::Result<bool> {
+		let mut chunk = [0u8; 4096];
+		loop {
+			match self.stream.read(&mut chunk) {
+				Ok(0) => return Ok(false),
+				Ok(n) => {
+					read_buf.extend_from_slice(&chunk[..n]);
+					while let Some(i) = read_buf.iter().position(|b| *b == b'\n') {
+						let line: Vec<u8> = read_buf.drain(..=i).collect();
+						let line = String::from_utf8_lossy(&line).trim().to_owned();
+						if line.is_empty() {
+							continue;
+						}
+						let message = types::ClientMessage::ServerMessage(self.id, line);
+						if client_tx.send(message).is_err() {
+							return Ok(false);
+						}
+					}
+				}
+				Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+				Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+				Err(e) => return Err(e),
+			}
+		}
+	}
+}
+
+/// Handle to a running connection thread. Dropping it closes the connection
+struct ConnectionHandle {
+	id: u64,
+	tx: mpsc::Sender<String>,
+	waker: Arc<Waker>,
+	closed: Arc<AtomicBool>,
+	thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ConnectionHandle {
+	/// Queue a message to be written to the server
+	fn send(&self, message: &str) -> Result<(), Error> {
+		self.tx
+			.send(message.to_owned())
+			.map_err(|_| Error::ConnectionError("broken pipe".to_string()))?;
+		self.waker
+			.wake()
+			.map_err(|e| Error::ConnectionError(format!("Can't wake connection: {}", e)))
+	}
+
+	/// Close the connection once everything queued has been written
+	fn close(mut self) {
+		self.closed.store(true, Ordering::Relaxed);
+		let _ = self.waker.wake();
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+impl Drop for ConnectionHandle {
+	fn drop(&mut self) {
+		self.closed.store(true, Ordering::Relaxed);
+		let _ = self.waker.wake();
+	}
+}
+
+/// Host part of a `host:port` server address, without the brackets
+/// around IPv6 addresses
+fn address_host(addr: &str) -> &str {
+	let host = match addr.rfind(':') {
+		Some(i) if !addr[..i].contains(':') || addr[..i].ends_with(']') => &addr[..i],
+		_ => addr,
+	};
+	host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Reads every certificate out of a PEM bundle
+fn read_pem_certificates(path: &Path) -> Result<Vec<Certificate>, Error> {
+	let pem = fs::read_to_string(path)
+		.map_err(|e| Error::TlsCaError(format!("Can't read CA file {}: {}", path.display(), e)))?;
+	let end_marker = "-----END CERTIFICATE-----";
+	let mut certs = vec![];
+	for block in pem.split_inclusive(end_marker) {
+		if !block.contains(end_marker) {
+			continue;
+		}
+		let cert = Certificate::from_pem(block.as_bytes()).map_err(|e| {
+			Error::TlsCaError(format!(
+				"Invalid certificate in {}: {:?}",
+				path.display(),
+				e
+			))
+		})?;
+		certs.push(cert);
+	}
+	if certs.is_empty() {
+		return Err(Error::TlsCaError(format!(
+			"No certificates found in {}",
+			path.display()
+		)));
+	}
+	Ok(certs)
+}
+
+/// Builds a TLS connector according to a server's TLS settings
+fn tls_connector(server: &config::StratumServerConfig) -> Result<TlsConnector, Error> {
+	let mut builder = TlsConnector::builder();
+	if let Some(path) = server.tls_ca_file.as_ref() {
+		for cert in read_pem_certificates(path)? {
+			builder.add_root_certificate(cert);
+		}
+	}
+	match (
+		server.tls_client_cert.as_ref(),
+		server.tls_client_key.as_ref(),
+	) {
+		(Some(cert_path), Some(key_path)) => {
+			let read = |path: &PathBuf| {
+				fs::read(path).map_err(|e| {
+					Error::TlsClientCertError(format!("Can't read {}: {}", path.display(), e))
+				})
+			};
+			let identity =
+				Identity::from_pkcs8(&read(cert_path)?, &read(key_path)?).map_err(|e| {
+					Error::TlsClientCertError(format!("Invalid client certificate or key: {:?}", e))
+				})?;
+			builder.identity(identity);
+		}
+		(None, None) => {}
+		_ => {
+			return Err(Error::TlsClientCertError(
+				"Both a client certificate and a client key are required".to_owned(),
+			));
+		}
+	}
+	if server.tls_insecure_skip_verify == Some(true) {
+		warn!(
+			LOGGER,
+			"TLS certificate verification is disabled for {}", server.addr
+		);
+		builder.danger_accept_invalid_certs(true);
+		builder.danger_accept_invalid_hostnames(true);
+	}
+	builder
+		.build()
+		.map_err(|e| Error::TlsConnectorError(format!("Can't create TLS connector: {:?}", e)))
+}
+
+/// A request sent to the server that hasn't been answered yet
+struct PendingRequest {
+	method: String,
+	/// the share, if this is a submit
+	share: Option<types::SubmitParams>,
+	sent_at: Instant,
+}
+
+impl PendingRequest {
+	fn new(method: &str) -> PendingRequest {
+		PendingRequest {
+			method: method.to_string(),
+			share: None,
+			sent_at: Instant::now(),
+		}
+	}
+
+	fn submit(share: types::SubmitParams) -> PendingRequest {
+		PendingRequest {
+			share: Some(share),
+			..PendingRequest::new("submit")
 		}
 	}
 }
 
 pub struct Controller {
 	_id: u32,
-	server_url: String,
-	server_login: Option<String>,
-	server_password: Option<String>,
-	server_tls_enabled: Option<bool>,
-	stream: Option<Stream>,
+	servers: Vec<config::StratumServerConfig>,
+	current_server: usize,
+	failed_connects: u32,
+	failover_attempts: u32,
+	job_timeout: i64,
+	last_job_time: i64,
+	primary_check_interval: i64,
+	/// Connection to the primary server being opened in the background
+	primary_probe: Option<mpsc::Receiver<Result<ConnectionHandle, Error>>>,
+	/// Server we've been told to move to by a "reconnect" message
+	redirect: Option<config::StratumServerConfig>,
+	next_server_retry: i64,
+	/// Current reconnect backoff, in seconds
+	retry_delay: f64,
+	reconnect_initial_delay: f64,
+	reconnect_max_delay: f64,
+	reconnect_multiplier: f64,
+	reconnect_jitter: f64,
+	/// Share difficulty set by the server, overriding the one in its jobs
+	pool_difficulty: Option<u64>,
+	/// Height of the last job the current server sent us that we took on
+	last_job_height: Option<u64>,
+	extranonce: Option<String>,
+	proxy: Option<Proxy>,
+	connection: Option<ConnectionHandle>,
+	next_connection_id: u64,
+	pending: HashMap<String, PendingRequest>,
+	request_timeout: Duration,
+	shutdown_timeout: Duration,
+	/// Shares found while we couldn't reach the server
+	offline_shares: VecDeque<types::SubmitParams>,
+	offline_queue_size: usize,
+	/// Set once we've been told to shut down, after which new work is ignored
+	shutting_down: bool,
 	rx: mpsc::Receiver<types::ClientMessage>,
 	pub tx: mpsc::Sender<types::ClientMessage>,
 	miner_tx: mpsc::Sender<types::MinerMessage>,
@@ -172,6 +550,31 @@ pub struct Controller {
 	stats: Arc<RwLock<stats::Stats>>,
 }
 
+/// Check a job is one the solvers can work on: its pre-pow has to decode
+/// and match the job's height, which can't go back, and it needs a difficulty
+fn validate_job(job: &types::JobTemplate, last_height: Option<u64>) -> Result<(), Error> {
+	let pre_pow =
+		PrePow::from_hex(&job.pre_pow).map_err(|e| Error::InvalidJobError(e.to_string()))?;
+	if pre_pow.height != job.height {
+		return Err(Error::InvalidJobError(format!(
+			"pre-pow is for height {}, job for height {}",
+			pre_pow.height, job.height
+		)));
+	}
+	if let Some(last_height) = last_height
+		&& job.height < last_height
+	{
+		return Err(Error::InvalidJobError(format!(
+			"height {} is below the previous job's {}",
+			job.height, last_height
+		)));
+	}
+	if job.difficulty == 0 {
+		return Err(Error::InvalidJobError("zero difficulty".to_owned()));
+	}
+	Ok(())
+}
+
 fn invalid_error_response() -> types::RpcError {
 	types::RpcError {
 		code: 0,
@@ -181,21 +584,49 @@ fn invalid_error_response() -> types::RpcError {
 
 impl Controller {
 	pub fn new(
-		server_url: &str,
-		server_login: Option<String>,
-		server_password: Option<String>,
-		server_tls_enabled: Option<bool>,
+		config: &config::MinerConfig,
 		miner_tx: mpsc::Sender<types::MinerMessage>,
 		stats: Arc<RwLock<stats::Stats>>,
 	) -> Result<Controller, Error> {
 		let (tx, rx) = mpsc::channel::<types::ClientMessage>();
+		let proxy = match config.stratum_proxy.as_ref() {
+			Some(url) => Some(Proxy::parse(url).map_err(Error::ProxyError)?),
+			None => None,
+		};
+		let reconnect_initial_delay = config.stratum_reconnect_initial_delay.unwrap_or(5) as f64;
 		Ok(Controller {
 			_id: 0,
-			server_url: server_url.to_string(),
-			server_login,
-			server_password,
-			server_tls_enabled,
-			stream: None,
+			servers: config.stratum_servers(),
+			current_server: 0,
+			failed_connects: 0,
+			failover_attempts: config.stratum_failover_attempts.unwrap_or(3),
+			job_timeout: config.stratum_job_timeout.unwrap_or(180) as i64,
+			last_job_time: time::get_time().sec,
+			primary_check_interval: config.stratum_primary_check_interval.unwrap_or(60) as i64,
+			primary_probe: None,
+			redirect: None,
+			next_server_retry: time::get_time().sec,
+			retry_delay: reconnect_initial_delay,
+			reconnect_initial_delay,
+			reconnect_max_delay: (config.stratum_reconnect_max_delay.unwrap_or(300) as f64)
+				.max(reconnect_initial_delay),
+			reconnect_multiplier: config.stratum_reconnect_multiplier.unwrap_or(2.0).max(1.0),
+			reconnect_jitter: config
+				.stratum_reconnect_jitter
+				.unwrap_or(0.2)
+				.clamp(0.0, 1.0),
+			pool_difficulty: None,
+			last_job_height: None,
+			extranonce: None,
+			proxy,
+			connection: None,
+			next_connection_id: 0,
+			pending: HashMap::new(),
+			request_timeout: Duration::from_secs(config.stratum_request_timeout.unwrap_or(30)),
+			shutdown_timeout: Duration::from_secs(config.stratum_shutdown_timeout.unwrap_or(5)),
+			shutting_down: false,
+			offline_shares: VecDeque::new(),
+			offline_queue_size: config.stratum_offline_queue_size.unwrap_or(32),
 			tx,
 			rx,
 			miner_tx,
@@ -204,75 +635,202 @@ impl Controller {
 		})
 	}
 
+	/// The stratum server we're currently connected (or connecting) to
+	fn server(&self) -> &config::StratumServerConfig {
+		match self.redirect.as_ref() {
+			Some(server) => server,
+			None => &self.servers[self.current_server],
+		}
+	}
+
+	/// Open a connection to the server at the given index, and start
+	/// its connection thread
+	fn open_connection(&mut self, index: usize) -> Result<ConnectionHandle, Error> {
+		self.next_connection_id += 1;
+		let server = if index == self.current_server {
+			self.server()
+		} else {
+			&self.servers[index]
+		};
+		Connection::open(self.next_connection_id, server, self.proxy.as_ref())?
+			.spawn(self.tx.clone())
+	}
+
 	pub fn try_connect(&mut self) -> Result<(), Error> {
-		self.stream = Some(Stream::new());
-		self.stream
-			.as_mut()
-			.unwrap()
-			.try_connect(&self.server_url, self.server_tls_enabled)?;
+		let connection = self.open_connection(self.current_server)?;
+		self.connection = Some(connection);
+		self.on_connected();
 		Ok(())
 	}
 
-	fn read_message(&mut self) -> Result<Option<String>, Error> {
-		if self.stream.is_none() {
-			return Err(Error::ConnectionError("broken pipe".to_string()));
+	/// Log in and ask for work on a newly established connection
+	fn on_connected(&mut self) {
+		let status = format!(
+			"Connection Status: Connected to Grin server at {}.",
+			self.server().addr
+		);
+		warn!(LOGGER, "{}", status);
+		self.failed_connects = 0;
+		self.last_job_time = time::get_time().sec;
+		{
+			let mut stats = self.stats.write().unwrap();
+			stats.client_stats.connection_status = status;
+			stats.client_stats.server_url = self.server().addr.clone();
 		}
-		let mut line = String::new();
-		match self.stream.as_mut().unwrap().read_line(&mut line) {
-			Ok(_) => {
-				// stream is not returning a proper error on disconnect
-				if line == "" {
-					return Err(Error::ConnectionError("broken pipe".to_string()));
-				}
-				Ok(Some(line))
+		let _ = self.send_login();
+		let _ = self.send_message_get_job_template();
+	}
+
+	/// Drop the current connection, if any, and stop the miners until
+	/// we have a job again. Reconnecting backs off like a failed connect,
+	/// so a server that takes connections only to drop them isn't hammered
+	fn disconnect(&mut self) {
+		if self.connection.take().is_some() {
+			self.next_server_retry = time::get_time().sec + self.next_retry_delay();
+			// answers to anything still outstanding are lost with the connection,
+			// so hold on to unanswered shares to submit again
+			let unanswered: Vec<types::SubmitParams> =
+				self.pending.drain().filter_map(|(_, p)| p.share).collect();
+			for share in unanswered {
+				self.queue_offline_share(share);
 			}
-			Err(ref e) if e.kind() == ErrorKind::BrokenPipe => {
-				Err(Error::ConnectionError("broken pipe".to_string()))
+			// as are any adjustments the server made to our work
+			self.pool_difficulty = None;
+			self.last_job_height = None;
+			if self.extranonce.take().is_some() {
+				let _ = self
+					.miner_tx
+					.send(types::MinerMessage::SetExtranonce(String::new()));
 			}
-			Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
-			Err(e) => {
-				error!(LOGGER, "Communication error with stratum server: {}", e);
-				Err(Error::ConnectionError("broken pipe".to_string()))
+			let _ = self.send_miner_stop();
+			let mut stats = self.stats.write().unwrap();
+			stats.client_stats.connected = false;
+		}
+	}
+
+	/// Drop the current connection and move on to the server at the given index
+	fn switch_server(&mut self, index: usize) {
+		let previous = self.server().addr.clone();
+		self.disconnect();
+		self.redirect = None;
+		self.current_server = index;
+		self.failed_connects = 0;
+		warn!(
+			LOGGER,
+			"Switching stratum server from {} to {}",
+			previous,
+			self.server().addr
+		);
+		let mut stats = self.stats.write().unwrap();
+		stats.client_stats.server_url = self.server().addr.clone();
+	}
+
+	/// Fail over to the next configured server, if there is one
+	fn fail_over(&mut self) {
+		if self.servers.len() > 1 {
+			let next = (self.current_server + 1) % self.servers.len();
+			self.switch_server(next);
+		} else {
+			self.disconnect();
+		}
+	}
+
+	/// Start connecting to the primary server on its own thread, so an
+	/// unreachable primary doesn't hold up the connection we're mining on
+	fn try_primary(&mut self) {
+		if self.primary_probe.is_some() {
+			return;
+		}
+		self.next_connection_id += 1;
+		let id = self.next_connection_id;
+		let server = self.servers[0].clone();
+		let proxy = self.proxy.clone();
+		let client_tx = self.tx.clone();
+		let (tx, rx) = mpsc::channel();
+		thread::spawn(move || {
+			let _ = tx.send(
+				Connection::open(id, &server, proxy.as_ref())
+					.and_then(|connection| connection.spawn(client_tx)),
+			);
+		});
+		self.primary_probe = Some(rx);
+	}
+
+	/// Go back to the primary server if the background connection to it
+	/// went through
+	fn check_primary_probe(&mut self) {
+		let result = match self.primary_probe.as_ref().map(|rx| rx.try_recv()) {
+			Some(Ok(result)) => result,
+			Some(Err(mpsc::TryRecvError::Empty)) | None => return,
+			Some(Err(mpsc::TryRecvError::Disconnected)) => {
+				self.primary_probe = None;
+				return;
+			}
+		};
+		self.primary_probe = None;
+		match result {
+			// dropping the connection closes it
+			Ok(_) if self.current_server == 0 || self.shutting_down => {}
+			Ok(connection) => {
+				self.switch_server(0);
+				self.connection = Some(connection);
+				self.on_connected();
 			}
+			Err(e) => debug!(
+				LOGGER,
+				"Primary stratum server {} still unavailable: {:?}", self.servers[0].addr, e
+			),
 		}
 	}
 
 	fn send_message(&mut self, message: &str) -> Result<(), Error> {
-		if self.stream.is_none() {
-			return Err(Error::ConnectionError(String::from("No server connection")));
+		match self.connection.as_ref() {
+			None => Err(Error::ConnectionError(String::from("No server connection"))),
+			Some(connection) => {
+				debug!(LOGGER, "sending request: {}", message);
+				connection.send(message)
+			}
 		}
-		debug!(LOGGER, "sending request: {}", message);
-		let _ = self.stream.as_mut().unwrap().write(message.as_bytes());
-		let _ = self.stream.as_mut().unwrap().write(b"\n");
-		let _ = self.stream.as_mut().unwrap().flush();
-		Ok(())
 	}
 
-	fn send_message_get_job_template(&mut self) -> Result<(), Error> {
+	/// Send a request to the server, keeping track of it until it's answered
+	fn send_request(
+		&mut self,
+		params: Option<serde_json::Value>,
+		pending: PendingRequest,
+	) -> Result<(), Error> {
+		self.last_request_id += 1;
+		let id = self.last_request_id.to_string();
 		let req = types::RpcRequest {
-			id: self.last_request_id.to_string(),
+			id: id.clone(),
 			jsonrpc: "2.0".to_string(),
-			method: "getjobtemplate".to_string(),
-			params: None,
+			method: pending.method.clone(),
+			params,
 		};
 		let req_str = serde_json::to_string(&req)?;
+		self.send_message(&req_str)?;
+		self.pending.insert(id, pending);
+		Ok(())
+	}
+
+	fn send_message_get_job_template(&mut self) -> Result<(), Error> {
 		{
 			let mut stats = self.stats.write()?;
 			stats.client_stats.last_message_sent = "Last Message Sent: Get New Job".to_string();
 		}
-		self.send_message(&req_str)
+		self.send_request(None, PendingRequest::new("getjobtemplate"))
 	}
 
 	fn send_login(&mut self) -> Result<(), Error> {
 		// only send the login request if a login string is configured
-		let login_str = match self.server_login.clone() {
+		let login_str = match self.server().login.clone() {
 			None => "".to_string(),
 			Some(server_login) => server_login,
 		};
 		if login_str == "" {
 			return Ok(());
 		}
-		let password_str = match self.server_password.clone() {
+		let password_str = match self.server().password.clone() {
 			None => "".to_string(),
 			Some(server_password) => server_password,
 		};
@@ -281,29 +839,18 @@ impl Controller {
 			pass: password_str,
 			agent: "grin-miner".to_string(),
 		};
-		let req = types::RpcRequest {
-			id: self.last_request_id.to_string(),
-			jsonrpc: "2.0".to_string(),
-			method: "login".to_string(),
-			params: Some(serde_json::to_value(params)?),
-		};
-		let req_str = serde_json::to_string(&req)?;
 		{
 			let mut stats = self.stats.write()?;
 			stats.client_stats.last_message_sent = "Last Message Sent: Login".to_string();
 		}
-		self.send_message(&req_str)
+		self.send_request(
+			Some(serde_json::to_value(params)?),
+			PendingRequest::new("login"),
+		)
 	}
 
 	fn send_message_get_status(&mut self) -> Result<(), Error> {
-		let req = types::RpcRequest {
-			id: self.last_request_id.to_string(),
-			jsonrpc: "2.0".to_string(),
-			method: "status".to_string(),
-			params: None,
-		};
-		let req_str = serde_json::to_string(&req)?;
-		self.send_message(&req_str)
+		self.send_request(None, PendingRequest::new("status"))
 	}
 
 	fn send_message_submit(
@@ -321,14 +868,6 @@ impl Controller {
 			nonce,
 			pow,
 		};
-		let params = serde_json::to_string(&params_in)?;
-		let req = types::RpcRequest {
-			id: self.last_request_id.to_string(),
-			jsonrpc: "2.0".to_string(),
-			method: "submit".to_string(),
-			params: Some(serde_json::from_str(&params)?),
-		};
-		let req_str = serde_json::to_string(&req)?;
 		{
 			let mut stats = self.stats.write()?;
 			stats.client_stats.last_message_sent = format!(
@@ -336,18 +875,144 @@ impl Controller {
 				params_in.height, params_in.nonce
 			);
 		}
-		self.send_message(&req_str)
+		self.submit_share(params_in)
 	}
 
-	fn send_miner_job(&mut self, job: types::JobTemplate) -> Result<(), Error> {
+	/// Submit a share, or hold on to it if we can't reach the server
+	fn submit_share(&mut self, share: types::SubmitParams) -> Result<(), Error> {
+		if self.connection.is_none() {
+			self.queue_offline_share(share);
+			return Ok(());
+		}
+		let params = serde_json::to_value(&share)?;
+		if let Err(e) = self.send_request(Some(params), PendingRequest::submit(share.clone())) {
+			self.queue_offline_share(share);
+			return Err(e);
+		}
+		Ok(())
+	}
+
+	/// Keep a share we couldn't submit until we're connected again, making
+	/// room by dropping the oldest one if the queue is full
+	fn queue_offline_share(&mut self, share: types::SubmitParams) {
+		if self.offline_shares.len() >= self.offline_queue_size {
+			let dropped = match self.offline_shares.pop_front() {
+				Some(oldest) => {
+					self.offline_shares.push_back(share);
+					oldest
+				}
+				None => share,
+			};
+			warn!(
+				LOGGER,
+				"Offline share queue full, dropping share for height {}, nonce {}",
+				dropped.height,
+				dropped.nonce
+			);
+			let mut stats = self.stats.write().unwrap();
+			stats.mining_stats.solution_stats.num_offline_dropped += 1;
+			return;
+		}
+		info!(
+			LOGGER,
+			"Holding share for height {}, nonce {} until we're reconnected",
+			share.height,
+			share.nonce
+		);
+		self.offline_shares.push_back(share);
+	}
+
+	/// Resubmit shares held while we were disconnected that are still for
+	/// the height we're mining at, and drop the rest as stale
+	fn resubmit_offline_shares(&mut self, height: u64) {
+		if self.connection.is_none() {
+			return;
+		}
+		while let Some(share) = self.offline_shares.pop_front() {
+			if share.height != height {
+				info!(
+					LOGGER,
+					"Dropping held share for height {}, nonce {}: now mining at height {}",
+					share.height,
+					share.nonce,
+					height
+				);
+				let mut stats = self.stats.write().unwrap();
+				stats.mining_stats.solution_stats.num_offline_dropped += 1;
+				continue;
+			}
+			info!(
+				LOGGER,
+				"Resubmitting held share for height {}, nonce {}", share.height, share.nonce
+			);
+			if self.submit_share(share).is_err() {
+				break;
+			}
+			let mut stats = self.stats.write().unwrap();
+			stats.mining_stats.solution_stats.num_offline_recovered += 1;
+		}
+	}
+
+	/// Give up on requests the server never answered
+	fn expire_requests(&mut self) {
+		let expired: Vec<String> = self
+			.pending
+			.iter()
+			.filter(|(_, p)| p.sent_at.elapsed() > self.request_timeout)
+			.map(|(id, _)| id.clone())
+			.collect();
+		for id in expired {
+			let p = self.pending.remove(&id).unwrap();
+			match p.share {
+				Some(share) => warn!(
+					LOGGER,
+					"No response to share submitted for height {} (job {}), nonce {}",
+					share.height,
+					share.job_id,
+					share.nonce
+				),
+				None => warn!(LOGGER, "No response to {} request {}", p.method, id),
+			}
+			let mut stats = self.stats.write().unwrap();
+			stats.client_stats.requests_timed_out += 1;
+		}
+	}
+
+	fn send_miner_job(&mut self, mut job: types::JobTemplate) -> Result<(), Error> {
+		if self.shutting_down {
+			debug!(
+				LOGGER,
+				"Shutting down, ignoring job at height {}", job.height
+			);
+			return Ok(());
+		}
+		if let Some(difficulty) = self.pool_difficulty {
+			job.difficulty = difficulty;
+		}
+		if let Err(e) = validate_job(&job, self.last_job_height) {
+			let mut stats = self.stats.write()?;
+			stats.client_stats.jobs_rejected += 1;
+			stats.client_stats.last_message_received = format!(
+				"Last Message Received: Rejected job for height {}: {:?}",
+				job.height, e
+			);
+			return Err(e);
+		}
+		self.last_job_time = time::get_time().sec;
+		self.last_job_height = Some(job.height);
+		self.retry_delay = self.reconnect_initial_delay;
 		let miner_message =
 			types::MinerMessage::ReceivedJob(job.height, job.job_id, job.difficulty, job.pre_pow);
-		let mut stats = self.stats.write()?;
-		stats.client_stats.last_message_received = format!(
-			"Last Message Received: Start Job for Height: {}, Difficulty: {}",
-			job.height, job.difficulty
-		);
-		self.miner_tx.send(miner_message).map_err(|e| e.into())
+		{
+			let mut stats = self.stats.write()?;
+			stats.client_stats.last_message_received = format!(
+				"Last Message Received: Start Job for Height: {}, Difficulty: {}",
+				job.height, job.difficulty
+			);
+		}
+		self.miner_tx.send(miner_message)?;
+		self.resubmit_offline_shares(job.height);
+		Ok(())
 	}
 
 	fn send_miner_stop(&mut self) -> Result<(), Error> {
@@ -366,13 +1031,139 @@ impl Controller {
 					self.send_miner_job(job)
 				}
 			},
-			_ => Err(Error::RequestError("Unknonw method".to_owned())),
+			"mining.set_difficulty" | "set_difficulty" => {
+				let params = types::SetDifficultyParams::from_params(
+					req.params.unwrap_or(serde_json::Value::Null),
+				)?;
+				if params.difficulty == 0 {
+					return Err(Error::RequestError("Zero share difficulty".to_owned()));
+				}
+				info!(
+					LOGGER,
+					"Server set share difficulty to {}", params.difficulty
+				);
+				self.pool_difficulty = Some(params.difficulty);
+				let mut stats = self.stats.write()?;
+				stats.client_stats.last_message_received = format!(
+					"Last Message Received: Share difficulty set to {}",
+					params.difficulty
+				);
+				self.miner_tx
+					.send(types::MinerMessage::SetDifficulty(params.difficulty))
+					.map_err(|e| e.into())
+			}
+			"mining.set_extranonce" | "set_extranonce" => {
+				let params = types::SetExtranonceParams::from_params(
+					req.params.unwrap_or(serde_json::Value::Null),
+				)?;
+				let extranonce = params.extranonce.trim_start_matches("0x").to_owned();
+				// leave at least one byte of the nonce for us to roll
+				if extranonce.len() % 2 != 0
+					|| extranonce.len() >= 16
+					|| !extranonce.chars().all(|c| c.is_ascii_hexdigit())
+				{
+					return Err(Error::RequestError(format!(
+						"Invalid extranonce: {}",
+						params.extranonce
+					)));
+				}
+				info!(LOGGER, "Server set extranonce to {}", extranonce);
+				self.extranonce = Some(extranonce.clone());
+				self.miner_tx
+					.send(types::MinerMessage::SetExtranonce(extranonce))
+					.map_err(|e| e.into())
+			}
+			"client.reconnect" | "reconnect" => {
+				let params = types::ReconnectParams::from_params(
+					req.params.unwrap_or(serde_json::json!({})),
+				)?;
+				self.reconnect(params);
+				Ok(())
+			}
+			_ => Err(Error::RequestError(format!(
+				"Unknown method {}",
+				req.method
+			))),
 		}
 	}
 
+	/// Honour a server's request to reconnect, possibly to another host or port
+	fn reconnect(&mut self, params: types::ReconnectParams) {
+		let mut server = self.server().clone();
+		let (host, port) = match server.addr.rsplit_once(':') {
+			Some((host, port)) => (host.to_owned(), port.to_owned()),
+			None => (server.addr.clone(), String::new()),
+		};
+		let host = match params.host {
+			Some(h) if !h.is_empty() => {
+				// the configured TLS name was meant for the old host
+				if h != host {
+					server.tls_server_name = None;
+				}
+				if h.contains(':') && !h.starts_with('[') {
+					format!("[{}]", h)
+				} else {
+					h
+				}
+			}
+			_ => host,
+		};
+		let port = params.port.map(|p| p.to_string()).unwrap_or(port);
+		server.addr = format!("{}:{}", host, port);
+		let wait = params.wait.unwrap_or(0) as i64;
+		warn!(
+			LOGGER,
+			"Server asked us to reconnect to {} in {} seconds", server.addr, wait
+		);
+		self.disconnect();
+		{
+			let mut stats = self.stats.write().unwrap();
+			stats.client_stats.server_url = server.addr.clone();
+			stats.client_stats.connection_status = format!(
+				"Connection Status: Reconnecting to {} as requested by the server",
+				server.addr
+			);
+		}
+		self.redirect = Some(server);
+		self.failed_connects = 0;
+		self.next_server_retry = time::get_time().sec + wait;
+	}
+
 	pub fn handle_response(&mut self, res: types::RpcResponse) -> Result<(), Error> {
 		debug!(LOGGER, "Received response with id: {}", res.id);
-		match res.method.as_str() {
+		// Work out which request this answers from its id, as not every
+		// server echoes the method back
+		let pending = self.pending.remove(&res.id);
+		let method = match (pending.as_ref(), res.method.as_ref()) {
+			(Some(p), _) => p.method.clone(),
+			(None, Some(m)) => m.clone(),
+			(None, None) => String::new(),
+		};
+		let share = match pending.as_ref() {
+			Some(p) => match p.share.as_ref() {
+				Some(share) => format!(
+					" for height {} (job {}), nonce {}",
+					share.height, share.job_id, share.nonce
+				),
+				None => String::new(),
+			},
+			None => String::new(),
+		};
+		if let Some(p) = pending.as_ref() {
+			let ms = p.sent_at.elapsed().as_secs_f64() * 1000.0;
+			let mut stats = self.stats.write()?;
+			let latency = match p.method.as_str() {
+				"submit" => Some(&mut stats.client_stats.submit_latency),
+				"login" => Some(&mut stats.client_stats.login_latency),
+				"getjobtemplate" => Some(&mut stats.client_stats.getjobtemplate_latency),
+				"status" => Some(&mut stats.client_stats.status_latency),
+				_ => None,
+			};
+			if let Some(l) = latency {
+				l.add(ms);
+			}
+		}
+		match method.as_str() {
 			// "status" response can be used to further populate stats object
 			"status" => {
 				if let Some(result) = res.result {
@@ -393,6 +1184,24 @@ impl Controller {
 						"Last Message Received: Accepted: {}, Rejected: {}, Stale: {}",
 						st.accepted, st.rejected, st.stale
 					);
+					let cs = &stats.client_stats;
+					info!(
+						LOGGER,
+						"Latency - submit: {}, getjobtemplate: {}, status: {}, login: {}",
+						cs.submit_latency,
+						cs.getjobtemplate_latency,
+						cs.status_latency,
+						cs.login_latency
+					);
+					info!(
+						LOGGER,
+						"Rejects - too late: {}, low difficulty: {}, invalid: {}, not ready: {}, other: {}",
+						cs.rejects.too_late,
+						cs.rejects.low_difficulty,
+						cs.rejects.invalid,
+						cs.rejects.not_ready,
+						cs.rejects.other
+					);
 				} else {
 					let err = res.error.unwrap_or_else(invalid_error_response);
 					let mut stats = self.stats.write()?;
@@ -432,7 +1241,7 @@ impl Controller {
 			// "submit" response
 			"submit" => {
 				if let Some(result) = res.result {
-					info!(LOGGER, "Share Accepted!!");
+					info!(LOGGER, "Share Accepted{}!!", share);
 					let mut stats = self.stats.write()?;
 					stats.client_stats.last_message_received =
 						"Last Message Received: Share Accepted!!".to_string();
@@ -451,12 +1260,14 @@ impl Controller {
 						"Last Message Received: Failed to submit a solution: {:?}",
 						err.message
 					);
-					if err.message.contains("too late") {
+					let reason = err.reject_reason();
+					stats.client_stats.rejects.add(reason);
+					if reason == types::RejectReason::TooLate {
 						stats.mining_stats.solution_stats.num_staled += 1;
 					} else {
 						stats.mining_stats.solution_stats.num_rejected += 1;
 					}
-					error!(LOGGER, "Failed to submit a solution: {:?}", err);
+					error!(LOGGER, "Failed to submit a solution{}: {:?}", share, err);
 				}
 				Ok(())
 			}
@@ -479,8 +1290,9 @@ impl Controller {
 			// "login" response
 			"login" => {
 				if res.result.is_some() {
-					// Nothing to do for login "ok"
-					// dont update last_message_received with good login response
+					// dont update last_message_received with good login response,
+					// but the server has taken us on, so start backing off afresh
+					self.retry_delay = self.reconnect_initial_delay;
 				} else {
 					// This is a fatal error
 					let err = res.error.unwrap_or_else(invalid_error_response);
@@ -505,154 +1317,439 @@ impl Controller {
 		}
 	}
 
+	/// Dispatch a line received from the server
+	fn handle_server_message(&mut self, m: String) {
+		{
+			let mut stats = self.stats.write().unwrap();
+			stats.client_stats.connected = true;
+		}
+		// figure out what kind of message,
+		// and dispatch appropriately
+		debug!(LOGGER, "Received message: {}", m);
+		// Deserialize to see what type of object it is
+		if let Ok(v) = serde_json::from_str::<serde_json::Value>(&m) {
+			// Is this a response or request? Requests pushed by the server
+			// have a method but no result or error
+			if v["method"].is_string() && v.get("result").is_none() && v.get("error").is_none() {
+				// this is a request
+				match serde_json::from_str::<types::RpcRequest>(&m) {
+					Err(e) => error!(LOGGER, "Error parsing request {} : {:?}", m, e),
+					Ok(request) => {
+						if let Err(err) = self.handle_request(request) {
+							error!(LOGGER, "Error handling request {} : :{:?}", m, err)
+						}
+					}
+				}
+			} else {
+				// this is a response
+				match serde_json::from_str::<types::RpcResponse>(&m) {
+					Err(e) => error!(LOGGER, "Error parsing response {} : {:?}", m, e),
+					Ok(response) => {
+						if let Err(err) = self.handle_response(response) {
+							error!(LOGGER, "Error handling response {} : :{:?}", m, err)
+						}
+					}
+				}
+			}
+		} else {
+			error!(LOGGER, "Error parsing message: {}", m)
+		}
+	}
+
+	fn handle_message(&mut self, message: types::ClientMessage) -> Result<(), Error> {
+		match message {
+			types::ClientMessage::ServerMessage(id, line) => {
+				if self.connection.as_ref().map(|c| c.id) == Some(id) {
+					self.handle_server_message(line);
+				}
+				Ok(())
+			}
+			types::ClientMessage::ServerDisconnected(id) => {
+				if self.connection.as_ref().map(|c| c.id) == Some(id) {
+					error!(LOGGER, "Lost connection to {}", self.server().addr);
+					self.disconnect();
+				}
+				Ok(())
+			}
+			types::ClientMessage::FoundSolution(height, job_id, edge_bits, nonce, pow) => {
+				debug!(
+					LOGGER,
+					"Client received solution for height {}, nonce {}", height, nonce
+				);
+				self.send_message_submit(height, job_id, edge_bits, nonce, pow)
+			}
+			types::ClientMessage::Shutdown => Ok(()),
+		}
+	}
+
+	/// Submit anything still queued, wait a while for the server to answer
+	/// our submits, then close the connection
+	fn shutdown(&mut self) {
+		self.shutting_down = true;
+		let deadline = Instant::now() + self.shutdown_timeout;
+		loop {
+			while let Ok(message) = self.rx.try_recv() {
+				if let Err(e) = self.handle_message(message) {
+					error!(LOGGER, "Error while shutting down: {:?}", e);
+				}
+			}
+			let waiting = self
+				.pending
+				.values()
+				.filter(|p| p.method == "submit")
+				.count();
+			if waiting == 0 || self.connection.is_none() {
+				break;
+			}
+			let now = Instant::now();
+			if now >= deadline {
+				warn!(
+					LOGGER,
+					"Shutting down without a response to {} share(s)", waiting
+				);
+				break;
+			}
+			match self.rx.recv_timeout(deadline - now) {
+				Ok(message) => {
+					if let Err(e) = self.handle_message(message) {
+						error!(LOGGER, "Error while shutting down: {:?}", e);
+					}
+				}
+				Err(mpsc::RecvTimeoutError::Timeout) => {}
+				Err(mpsc::RecvTimeoutError::Disconnected) => break,
+			}
+		}
+		if !self.offline_shares.is_empty() {
+			warn!(
+				LOGGER,
+				"Shutting down with {} share(s) that couldn't be submitted",
+				self.offline_shares.len()
+			);
+		}
+		// grin's stratum has no logout, closing the connection is all there is
+		if let Some(connection) = self.connection.take() {
+			connection.close();
+			info!(LOGGER, "Disconnected from {}", self.server().addr);
+		}
+		let mut stats = self.stats.write().unwrap();
+		stats.client_stats.connected = false;
+		stats.client_stats.connection_status = "Connection Status: Shut down".to_string();
+	}
+
+	/// Seconds to wait before the next reconnect attempt. Grows with each
+	/// failed attempt, with some randomness so a fleet of rigs doesn't
+	/// reconnect to a restarted pool in lockstep
+	fn next_retry_delay(&mut self) -> i64 {
+		let jitter = self.reconnect_jitter * (2.0 * rand::random::<f64>() - 1.0);
+		let delay = (self.retry_delay * (1.0 + jitter)).round().max(1.0);
+		self.retry_delay =
+			(self.retry_delay * self.reconnect_multiplier).min(self.reconnect_max_delay);
+		delay as i64
+	}
+
 	pub fn run(mut self) {
-		let server_read_interval = 1;
-		let server_retry_interval = 5;
-		let mut next_server_read = time::get_time().sec + server_read_interval;
 		let status_interval = 30;
 		let mut next_status_request = time::get_time().sec + status_interval;
-		let mut next_server_retry = time::get_time().sec;
+		let mut next_primary_check = time::get_time().sec + self.primary_check_interval;
 		// Request the first job template
 		thread::sleep(std::time::Duration::from_secs(1));
-		let mut was_disconnected = true;
 		loop {
+			self.check_primary_probe();
 			// Check our connection status, and try to correct if possible
-			if self.stream.is_none() {
-				if !was_disconnected {
-					let _ = self.send_miner_stop();
-				}
-				was_disconnected = true;
-				if time::get_time().sec > next_server_retry {
-					if self.try_connect().is_err() {
-						let status = format!(
-							"Connection Status: Can't establish server connection to {}. Will retry every {} seconds",
-							self.server_url, server_retry_interval
-						);
-						warn!(LOGGER, "{}", status);
+			if self.connection.is_none() {
+				if time::get_time().sec >= self.next_server_retry
+					&& let Err(e) = self.try_connect()
+				{
+					self.failed_connects += 1;
+					let delay = self.next_retry_delay();
+					self.next_server_retry = time::get_time().sec + delay;
+					let next_attempt = time::at(time::Timespec::new(self.next_server_retry, 0));
+					let status = format!(
+						"Connection Status: Can't establish server connection to {}. Attempt {} failed, retrying in {}s (at {})",
+						self.server().addr,
+						self.failed_connects,
+						delay,
+						time::strftime("%H:%M:%S", &next_attempt).unwrap_or_default()
+					);
+					warn!(LOGGER, "{} ({:?})", status, e);
+					{
 						let mut stats = self.stats.write().unwrap();
 						stats.client_stats.connection_status = status;
 						stats.client_stats.connected = false;
-						self.stream = None;
-					} else {
-						let status = format!(
-							"Connection Status: Connected to Grin server at {}.",
-							self.server_url
-						);
-						warn!(LOGGER, "{}", status);
-						let mut stats = self.stats.write().unwrap();
-						stats.client_stats.connection_status = status;
-					}
-					next_server_retry = time::get_time().sec + server_retry_interval;
-					if self.stream.is_none() {
-						thread::sleep(std::time::Duration::from_secs(1));
-						continue;
 					}
-				}
-			} else {
-				// get new job template
-				if was_disconnected {
-					let _ = self.send_login();
-					let _ = self.send_message_get_job_template();
-					was_disconnected = false;
-				}
-				// read messages from server
-				if time::get_time().sec > next_server_read {
-					match self.read_message() {
-						Ok(message) => {
-							match message {
-								Some(m) => {
-									{
-										let mut stats = self.stats.write().unwrap();
-										stats.client_stats.connected = true;
-									}
-									// figure out what kind of message,
-									// and dispatch appropriately
-									debug!(LOGGER, "Received message: {}", m);
-									// Deserialize to see what type of object it is
-									if let Ok(v) = serde_json::from_str::<serde_json::Value>(&m) {
-										// Is this a response or request?
-										if v["method"] == "job" {
-											// this is a request
-											match serde_json::from_str::<types::RpcRequest>(&m) {
-												Err(e) => error!(
-													LOGGER,
-													"Error parsing request {} : {:?}", m, e
-												),
-												Ok(request) => {
-													if let Err(err) = self.handle_request(request) {
-														error!(
-															LOGGER,
-															"Error handling request {} : :{:?}",
-															m,
-															err
-														)
-													}
-												}
-											}
-											continue;
-										} else {
-											// this is a response
-											match serde_json::from_str::<types::RpcResponse>(&m) {
-												Err(e) => error!(
-													LOGGER,
-													"Error parsing response {} : {:?}", m, e
-												),
-												Ok(response) => {
-													if let Err(err) = self.handle_response(response)
-													{
-														error!(
-															LOGGER,
-															"Error handling response {} : :{:?}",
-															m,
-															err
-														)
-													}
-												}
-											}
-											continue;
-										}
-									} else {
-										error!(LOGGER, "Error parsing message: {}", m)
-									}
-								}
-								None => {} // No messages from the server at this time
-							}
-						}
-						Err(e) => {
-							error!(LOGGER, "Error reading message: {:?}", e);
-							self.stream = None;
-							continue;
+					if self.failed_connects >= self.failover_attempts {
+						if let Some(redirect) = self.redirect.take() {
+							warn!(
+								LOGGER,
+								"Giving up on {}, going back to {}",
+								redirect.addr,
+								self.server().addr
+							);
+							self.failed_connects = 0;
+						} else {
+							self.fail_over();
 						}
 					}
-					next_server_read = time::get_time().sec + server_read_interval;
 				}
-
+			} else {
 				// Request a status message from the server
 				if time::get_time().sec > next_status_request {
 					let _ = self.send_message_get_status();
 					next_status_request = time::get_time().sec + status_interval;
 				}
-			}
 
-			// Talk to the cuckoo miner plugin
-			while let Some(message) = self.rx.try_iter().next() {
-				debug!(LOGGER, "Client received message: {:?}", message);
-				let result = match message {
-					types::ClientMessage::FoundSolution(height, job_id, edge_bits, nonce, pow) => {
-						self.send_message_submit(height, job_id, edge_bits, nonce, pow)
-					}
-					types::ClientMessage::Shutdown => {
-						//TODO: Inform server?
-						debug!(LOGGER, "Shutting down client controller");
-						return;
-					}
-				};
-				if let Err(e) = result {
-					error!(LOGGER, "Mining Controller Error {:?}", e);
-					self.stream = None;
+				self.expire_requests();
+
+				// Move on if the server has stopped feeding us jobs
+				if time::get_time().sec > self.last_job_time + self.job_timeout {
+					warn!(
+						LOGGER,
+						"No job received from {} in {} seconds",
+						self.server().addr,
+						self.job_timeout
+					);
+					self.fail_over();
+					continue;
+				}
+
+				// Go back to the primary server once it's healthy again
+				if self.current_server != 0 && time::get_time().sec > next_primary_check {
+					self.try_primary();
+					next_primary_check = time::get_time().sec + self.primary_check_interval;
 				}
 			}
-			thread::sleep(std::time::Duration::from_millis(10));
+
+			// Block until the server or the miner has something for us, waking
+			// up regularly to take care of the timers above
+			let message = match self.rx.recv_timeout(Duration::from_secs(1)) {
+				Ok(message) => message,
+				Err(mpsc::RecvTimeoutError::Timeout) => continue,
+				Err(mpsc::RecvTimeoutError::Disconnected) => return,
+			};
+			if let types::ClientMessage::Shutdown = message {
+				debug!(LOGGER, "Shutting down client controller");
+				self.shutdown();
+				return;
+			}
+			if let Err(e) = self.handle_message(message) {
+				error!(LOGGER, "Mining Controller Error {:?}", e);
+				self.disconnect();
+			}
 		} // loop
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde_json::json;
+
+	fn controller(config: config::MinerConfig) -> Controller {
+		let (miner_tx, _) = mpsc::channel();
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		Controller::new(&config, miner_tx, stats).unwrap()
+	}
+
+	/// Stand in for a connection, handing back what's written to it
+	fn connect(controller: &mut Controller) -> (mpsc::Receiver<String>, Poll) {
+		let poll = Poll::new().unwrap();
+		let (tx, rx) = mpsc::channel();
+		controller.connection = Some(ConnectionHandle {
+			id: 1,
+			tx,
+			waker: Arc::new(Waker::new(poll.registry(), Token(0)).unwrap()),
+			closed: Arc::new(AtomicBool::new(false)),
+			thread: None,
+		});
+		(rx, poll)
+	}
+
+	fn sent(rx: &mpsc::Receiver<String>) -> Vec<types::RpcRequest> {
+		rx.try_iter()
+			.map(|m| serde_json::from_str(&m).unwrap())
+			.collect()
+	}
+
+	fn share(height: u64, nonce: u64) -> types::SubmitParams {
+		types::SubmitParams {
+			height,
+			job_id: 0,
+			edge_bits: 29,
+			nonce,
+			pow: vec![],
+		}
+	}
+
+	fn response(id: &str, result: Option<serde_json::Value>) -> types::RpcResponse {
+		types::RpcResponse {
+			id: id.to_owned(),
+			method: None,
+			jsonrpc: "2.0".to_owned(),
+			result,
+			error: None,
+		}
+	}
+
+	#[test]
+	fn responses_are_matched_to_requests_by_id() {
+		let mut c = controller(config::MinerConfig::default());
+		let (rx, _poll) = connect(&mut c);
+		c.send_message_get_status().unwrap();
+		c.submit_share(share(10, 1)).unwrap();
+		let ids: Vec<(String, String)> = sent(&rx).into_iter().map(|r| (r.id, r.method)).collect();
+		assert_eq!(
+			ids,
+			vec![
+				("1".to_owned(), "status".to_owned()),
+				("2".to_owned(), "submit".to_owned())
+			]
+		);
+
+		// answered out of order, without the method echoed back
+		c.handle_response(response("2", Some(json!("ok")))).unwrap();
+		assert!(!c.pending.contains_key("2"));
+		assert!(c.pending.contains_key("1"));
+		{
+			let stats = c.stats.read().unwrap();
+			assert_eq!(stats.mining_stats.solution_stats.num_shares_accepted, 1);
+			assert_eq!(stats.client_stats.submit_latency.count(), 1);
+			assert_eq!(stats.client_stats.status_latency.count(), 0);
+		}
+
+		// an id we never sent is no request of ours
+		c.handle_response(response("7", Some(json!("ok")))).unwrap();
+		assert!(c.pending.contains_key("1"));
+		let stats = c.stats.read().unwrap();
+		assert_eq!(stats.mining_stats.solution_stats.num_shares_accepted, 1);
+	}
+
+	#[test]
+	fn expires_only_requests_past_the_timeout() {
+		let mut c = controller(config::MinerConfig::default());
+		let stale = PendingRequest {
+			sent_at: Instant::now() - c.request_timeout - Duration::from_secs(1),
+			..PendingRequest::submit(share(10, 1))
+		};
+		c.pending.insert("1".to_owned(), stale);
+		c.pending
+			.insert("2".to_owned(), PendingRequest::new("getjobtemplate"));
+		c.expire_requests();
+		assert!(!c.pending.contains_key("1"));
+		assert!(c.pending.contains_key("2"));
+		assert_eq!(c.stats.read().unwrap().client_stats.requests_timed_out, 1);
+	}
+
+	#[test]
+	fn offline_queue_drops_the_oldest_shares() {
+		let mut c = controller(config::MinerConfig {
+			stratum_offline_queue_size: Some(3),
+			..Default::default()
+		});
+		for nonce in 1..=5 {
+			c.submit_share(share(10, nonce)).unwrap();
+		}
+		let nonces: Vec<u64> = c.offline_shares.iter().map(|s| s.nonce).collect();
+		assert_eq!(nonces, vec![3, 4, 5]);
+		let stats = c.stats.read().unwrap();
+		assert_eq!(stats.mining_stats.solution_stats.num_offline_dropped, 2);
+	}
+
+	#[test]
+	fn resubmits_held_shares_in_order_for_the_current_height() {
+		let mut c = controller(config::MinerConfig::default());
+		for (height, nonce) in [(10, 1), (9, 2), (10, 3), (10, 4)] {
+			c.submit_share(share(height, nonce)).unwrap();
+		}
+		// nothing to resubmit to yet
+		c.resubmit_offline_shares(10);
+		assert_eq!(c.offline_shares.len(), 4);
+
+		let (rx, _poll) = connect(&mut c);
+		c.resubmit_offline_shares(10);
+		assert!(c.offline_shares.is_empty());
+		let nonces: Vec<u64> = sent(&rx)
+			.into_iter()
+			.map(|r| serde_json::from_value::<types::SubmitParams>(r.params.unwrap()).unwrap())
+			.map(|s| s.nonce)
+			.collect();
+		assert_eq!(nonces, vec![1, 3, 4]);
+		let stats = c.stats.read().unwrap();
+		assert_eq!(stats.mining_stats.solution_stats.num_offline_recovered, 3);
+		assert_eq!(stats.mining_stats.solution_stats.num_offline_dropped, 1);
+	}
+
+	#[test]
+	fn retry_delay_backs_off_within_the_jitter() {
+		let config = config::MinerConfig {
+			stratum_reconnect_initial_delay: Some(5),
+			stratum_reconnect_max_delay: Some(40),
+			stratum_reconnect_multiplier: Some(2.0),
+			stratum_reconnect_jitter: Some(0.2),
+			..Default::default()
+		};
+		let mut c = controller(config.clone());
+		for _ in 0..100 {
+			c.retry_delay = c.reconnect_initial_delay;
+			for base in [5.0, 10.0, 20.0, 40.0, 40.0] {
+				let delay = c.next_retry_delay() as f64;
+				assert!(delay >= (base * 0.8_f64).round(), "{} for {}", delay, base);
+				assert!(delay <= (base * 1.2_f64).round(), "{} for {}", delay, base);
+			}
+		}
+
+		let mut c = controller(config::MinerConfig {
+			stratum_reconnect_jitter: Some(0.0),
+			..config
+		});
+		let delays: Vec<i64> = (0..5).map(|_| c.next_retry_delay()).collect();
+		assert_eq!(delays, vec![5, 10, 20, 40, 40]);
+
+		// never retry straight away
+		let mut c = controller(config::MinerConfig {
+			stratum_reconnect_initial_delay: Some(0),
+			..Default::default()
+		});
+		assert_eq!(c.next_retry_delay(), 1);
+	}
+
+	fn job(height: u64, pre_pow_height: u64, difficulty: u64) -> types::JobTemplate {
+		let pre_pow = PrePow {
+			height: pre_pow_height,
+			..Default::default()
+		};
+		types::JobTemplate {
+			height,
+			job_id: 0,
+			difficulty,
+			pre_pow: pre_pow.to_hex(),
+		}
+	}
+
+	#[test]
+	fn validates_jobs() {
+		assert!(validate_job(&job(10, 10, 1), None).is_ok());
+		assert!(validate_job(&job(10, 10, 1), Some(10)).is_ok());
+		assert!(validate_job(&job(11, 11, 1), Some(10)).is_ok());
+
+		let invalid = |job: &types::JobTemplate, last_height| {
+			matches!(
+				validate_job(job, last_height),
+				Err(Error::InvalidJobError(_))
+			)
+		};
+		// going back
+		assert!(invalid(&job(9, 9, 1), Some(10)));
+		// no difficulty
+		assert!(invalid(&job(10, 10, 0), None));
+		// pre-pow for another block
+		assert!(invalid(&job(10, 11, 1), None));
+		// truncated, odd length or not hex at all
+		let mut bad = job(10, 10, 1);
+		bad.pre_pow.truncate(bad.pre_pow.len() - 8);
+		assert!(invalid(&bad, None));
+		bad.pre_pow.pop();
+		assert!(invalid(&bad, None));
+		bad.pre_pow = "not hex".to_owned();
+		assert!(invalid(&bad, None));
+	}
+}
@@ -19,8 +19,10 @@ extern crate grin_miner_config as config;
 extern crate grin_miner_plugin as plugin;
 extern crate grin_miner_util as util;
 
-extern crate bufstream;
+extern crate ctrlc;
+extern crate mio;
 extern crate native_tls;
+extern crate rand;
 extern crate time;
 #[macro_use]
 extern crate serde_derive;
@@ -33,6 +35,7 @@ extern crate cursive;
 
 pub mod client;
 pub mod mining;
+pub mod proxy;
 pub mod stats;
 pub mod types;
 
@@ -43,6 +46,7 @@ use config::GlobalConfig;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
 use std::thread;
+use std::time::Duration;
 
 use util::{LOGGER, init_logger};
 
@@ -103,9 +107,11 @@ mod with_tui {
 					panic!("Error loading UI controller: {}", e);
 				});
 				controller.run(s.clone());
-				// Shut down everything else on tui exit
-				let _ = client_tx.send(types::ClientMessage::Shutdown);
-				let _ = miner_tx.send(types::MinerMessage::Shutdown);
+				// Shut down everything else on tui exit. The miner tells the
+				// client to shut down once it has handed over its last solutions
+				if miner_tx.send(types::MinerMessage::Shutdown).is_err() {
+					let _ = client_tx.send(types::ClientMessage::Shutdown);
+				}
 				stop.store(true, Ordering::Relaxed);
 			});
 	}
@@ -145,17 +151,10 @@ fn main() {
 		mining::Controller::new(mining_config.clone(), stats.clone()).unwrap_or_else(|e| {
 			panic!("Error loading mining controller: {}", e);
 		});
-	let cc = client::Controller::new(
-		&mining_config.stratum_server_addr,
-		mining_config.stratum_server_login.clone(),
-		mining_config.stratum_server_password.clone(),
-		mining_config.stratum_server_tls_enabled,
-		mc.tx.clone(),
-		stats.clone(),
-	)
-	.unwrap_or_else(|e| {
-		panic!("Error loading stratum client controller: {:?}", e);
-	});
+	let cc =
+		client::Controller::new(&mining_config, mc.tx.clone(), stats.clone()).unwrap_or_else(|e| {
+			panic!("Error loading stratum client controller: {:?}", e);
+		});
 	let tui_stopped = Arc::new(AtomicBool::new(false));
 	let miner_stopped = Arc::new(AtomicBool::new(false));
 	let client_stopped = Arc::new(AtomicBool::new(false));
@@ -177,6 +176,37 @@ fn main() {
 			return;
 		}
 	};
+	if let Some(rig_id) = mining_config.rig_id {
+		miner.set_nonce_mode(cuckoo::NonceMode::Partitioned {
+			rig_id,
+			range: mining_config.nonce_range.unwrap_or(1),
+		});
+	}
+	let default_restart_policy = cuckoo::RestartPolicy::default();
+	miner.set_restart_policy(cuckoo::RestartPolicy {
+		delay: mining_config
+			.solver_restart_delay
+			.map_or(default_restart_policy.delay, Duration::from_secs),
+		max_delay: mining_config
+			.solver_restart_max_delay
+			.map_or(default_restart_policy.max_delay, Duration::from_secs),
+		max_per_hour: mining_config
+			.solver_max_restarts_per_hour
+			.unwrap_or(default_restart_policy.max_per_hour),
+	});
+	let default_watchdog_policy = cuckoo::WatchdogPolicy::default();
+	miner.set_watchdog_policy(cuckoo::WatchdogPolicy {
+		multiple: mining_config
+			.solver_watchdog_multiple
+			.unwrap_or(default_watchdog_policy.multiple),
+		min_time: mining_config
+			.solver_watchdog_min_time
+			.map_or(default_watchdog_policy.min_time, Duration::from_secs),
+		shutdown_timeout: mining_config.solver_shutdown_timeout.map_or(
+			default_watchdog_policy.shutdown_timeout,
+			Duration::from_secs,
+		),
+	});
 	if let Err(e) = miner.start_solvers() {
 		println!("Error starting plugins. Please check logs for further info.");
 		println!("Error details:");
@@ -193,6 +223,17 @@ fn main() {
 		warn!(LOGGER, "Grin-miner was built with TUI support disabled!");
 	} else {
 		tui_stopped.store(true, Ordering::Relaxed);
+		// without the TUI to quit from, shut down just as orderly on
+		// Ctrl-C or SIGTERM
+		let miner_tx = mc.tx.clone();
+		let client_tx = cc.tx.clone();
+		if let Err(e) = ctrlc::set_handler(move || {
+			if miner_tx.send(types::MinerMessage::Shutdown).is_err() {
+				let _ = client_tx.send(types::ClientMessage::Shutdown);
+			}
+		}) {
+			warn!(LOGGER, "Can't install a shutdown signal handler: {}", e);
+		}
 	}
 
 	mc.set_client_tx(cc.tx.clone());
@@ -13,17 +13,68 @@
 // limitations under the License.
 
 use crate::{config, stats, types};
+use std;
 /// Plugin controller, listens for messages sent from the stratum
 /// server, controls plugins and responds appropriately
 use std::sync::{Arc, RwLock, mpsc};
-use std::{self, thread};
 use time;
 use util::LOGGER;
 
-use cuckoo::{CuckooMiner, CuckooMinerError};
+use cuckoo::{CuckooMiner, CuckooMinerError, JobSolutions, PrePow};
 
 use plugin::SolverStats;
 
+/// Times of day a device should mine
+struct Schedule {
+	/// id of the device's solver
+	id: usize,
+	/// start and end of each window, in minutes past midnight. A window
+	/// ending before it starts runs past midnight
+	windows: Vec<(u32, u32)>,
+	/// whether the device was last set to mine
+	mining: Option<bool>,
+}
+
+impl Schedule {
+	/// Parse comma separated HH:MM-HH:MM windows
+	fn parse(id: usize, schedule: &str) -> Result<Schedule, String> {
+		let parse_time = |t: &str| -> Option<u32> {
+			let (hours, minutes) = t.trim().split_once(':')?;
+			let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+			if hours < 24 && minutes < 60 {
+				Some(hours * 60 + minutes)
+			} else {
+				None
+			}
+		};
+		let mut windows = vec![];
+		for window in schedule.split(',') {
+			let times = window
+				.split_once('-')
+				.and_then(|(start, end)| Some((parse_time(start)?, parse_time(end)?)));
+			match times {
+				Some((start, end)) if start != end => windows.push((start, end)),
+				_ => return Err(format!("Invalid schedule window: {}", window.trim())),
+			}
+		}
+		Ok(Schedule {
+			id,
+			windows,
+			mining: None,
+		})
+	}
+
+	fn contains(&self, minute: u32) -> bool {
+		self.windows.iter().any(|&(start, end)| {
+			if start < end {
+				minute >= start && minute < end
+			} else {
+				minute >= start || minute < end
+			}
+		})
+	}
+}
+
 pub struct Controller {
 	_config: config::MinerConfig,
 	rx: mpsc::Receiver<types::MinerMessage>,
@@ -32,6 +83,9 @@ pub struct Controller {
 	current_height: u64,
 	current_job_id: u64,
 	current_target_diff: u64,
+	current_pre_pow: Option<PrePow>,
+	stale_policy: config::StaleSolutionPolicy,
+	schedules: Vec<Schedule>,
 	stats: Arc<RwLock<stats::Stats>>,
 }
 
@@ -45,6 +99,16 @@ impl Controller {
 			stats_w.client_stats.server_url = config.stratum_server_addr.clone();
 		}
 		let (tx, rx) = mpsc::channel::<types::MinerMessage>();
+		let stale_policy = config
+			.stale_solution_policy
+			.unwrap_or(config::StaleSolutionPolicy::SubmitSameHeight);
+		// solvers get their ids in the order they're configured
+		let mut schedules = vec![];
+		for (id, plugin_config) in config.miner_plugin_config.iter().enumerate() {
+			if let Some(schedule) = plugin_config.schedule.as_ref() {
+				schedules.push(Schedule::parse(id, schedule)?);
+			}
+		}
 		Ok(Controller {
 			_config: config,
 			rx,
@@ -53,6 +117,9 @@ impl Controller {
 			current_height: 0,
 			current_job_id: 0,
 			current_target_diff: 0,
+			current_pre_pow: None,
+			stale_policy,
+			schedules,
 			stats,
 		})
 	}
@@ -66,22 +133,35 @@ impl Controller {
 		// how often to output stats
 		let stat_output_interval = 2;
 		let mut next_stat_output = time::get_time().sec + stat_output_interval;
+		self.apply_schedules(&miner);
 
 		loop {
 			while let Some(message) = self.rx.try_iter().next() {
 				debug!(LOGGER, "Miner received message: {:?}", message);
 				let result = match message {
 					types::MinerMessage::ReceivedJob(height, job_id, diff, pre_pow) => {
-						self.current_height = height;
-						self.current_job_id = job_id;
+						match PrePow::from_hex(&pre_pow) {
+							Ok(pre_pow) => {
+								self.current_height = height;
+								self.current_job_id = job_id;
+								self.current_target_diff = diff;
+								self.current_pre_pow = Some(pre_pow.clone());
+								miner.notify(
+									self.current_job_id as u32,
+									self.current_height,
+									pre_pow,
+									diff,
+								)
+							}
+							Err(e) => Err(e.into()),
+						}
+					}
+					types::MinerMessage::SetDifficulty(diff) => {
 						self.current_target_diff = diff;
-						miner.notify(
-							self.current_job_id as u32,
-							self.current_height,
-							&pre_pow,
-							"",
-							diff,
-						)
+						miner.set_difficulty(diff)
+					}
+					types::MinerMessage::SetExtranonce(extranonce) => {
+						miner.set_extranonce(&extranonce)
 					}
 					types::MinerMessage::StopJob => {
 						debug!(LOGGER, "Stopping jobs");
@@ -92,6 +172,11 @@ impl Controller {
 						debug!(LOGGER, "Stopping jobs and Shutting down mining controller");
 						miner.stop_solvers();
 						miner.wait_for_solver_shutdown();
+						// hand over the last solutions before the client goes
+						self.send_solutions(miner.get_solutions());
+						if let Some(client_tx) = self.client_tx.as_ref() {
+							let _ = client_tx.send(types::ClientMessage::Shutdown);
+						}
 						return Ok(());
 					}
 				};
@@ -102,29 +187,86 @@ impl Controller {
 
 			if time::get_time().sec > next_stat_output {
 				self.output_job_stats(miner.get_stats().unwrap());
+				self.apply_schedules(&miner);
 				next_stat_output = time::get_time().sec + stat_output_interval;
 			}
 
-			let solutions = miner.get_solutions();
-			if let Some(ss) = solutions {
-				let edge_bits = ss.edge_bits;
-				for i in 0..ss.num_sols {
-					let _ =
-						self.client_tx
-							.as_mut()
-							.unwrap()
-							.send(types::ClientMessage::FoundSolution(
-								self.current_height,
-								ss.sols[i as usize].id,
-								edge_bits,
-								ss.sols[i as usize].nonce,
-								ss.sols[i as usize].proof.to_vec(),
-							));
-				}
-				let mut s_stats = self.stats.write().unwrap();
-				s_stats.mining_stats.solution_stats.num_solutions_found += ss.num_sols;
+			// Wait for solutions, but come back regularly to check for messages
+			let solutions = miner.wait_for_solutions(std::time::Duration::from_millis(100));
+			self.send_solutions(solutions);
+		}
+	}
+
+	/// Pause or resume devices as their schedules say
+	fn apply_schedules(&mut self, miner: &CuckooMiner) {
+		let now = time::now();
+		let minute = (now.tm_hour * 60 + now.tm_min) as u32;
+		for schedule in self.schedules.iter_mut() {
+			let mining = schedule.contains(minute);
+			if schedule.mining == Some(mining) {
+				continue;
+			}
+			schedule.mining = Some(mining);
+			let result = if mining {
+				info!(
+					LOGGER,
+					"Device {} is scheduled to mine, resuming", schedule.id
+				);
+				miner.resume_solver(schedule.id)
+			} else {
+				info!(
+					LOGGER,
+					"Device {} is not scheduled to mine, pausing", schedule.id
+				);
+				miner.pause_solver(schedule.id)
+			};
+			if let Err(e) = result {
+				error!(LOGGER, "Error applying schedule: {:?}", e);
+			}
+		}
+	}
+
+	/// Pass any solutions found to the stratum client, unless they're for a
+	/// job that has since been replaced and the stale policy says to drop them
+	fn send_solutions(&mut self, solutions: Vec<JobSolutions>) {
+		for js in solutions {
+			let ss = js.solutions;
+			let current =
+				js.height == self.current_height && js.job_id as u64 == self.current_job_id;
+			let submit = current
+				|| match self.stale_policy {
+					config::StaleSolutionPolicy::Drop => false,
+					config::StaleSolutionPolicy::SubmitSameHeight => {
+						js.height == self.current_height
+					}
+					config::StaleSolutionPolicy::SubmitAll => true,
+				};
+			let mut s_stats = self.stats.write().unwrap();
+			s_stats.mining_stats.solution_stats.num_solutions_found += ss.num_sols;
+			if !submit {
+				debug!(
+					LOGGER,
+					"Discarding {} solution(s) for replaced job {} at height {}",
+					ss.num_sols,
+					js.job_id,
+					js.height
+				);
+				s_stats.mining_stats.solution_stats.num_discarded_stale += ss.num_sols;
+				continue;
+			}
+			for i in 0..ss.num_sols {
+				let _ = self
+					.client_tx
+					.as_mut()
+					.unwrap()
+					.send(types::ClientMessage::FoundSolution(
+						js.height,
+						js.job_id as u64,
+						ss.edge_bits,
+						ss.sols[i as usize].nonce,
+						ss.sols[i as usize].proof.to_vec(),
+					));
 			}
-			thread::sleep(std::time::Duration::from_millis(100));
 		}
 	}
 
@@ -134,12 +276,18 @@ impl Controller {
 		for s in stats.clone() {
 			let last_solution_time_secs = s.last_solution_time as f64 / 1_000_000_000.0;
 			let last_hashes_per_sec = 1.0 / last_solution_time_secs;
-			let status = if s.has_errored { "ERRORED" } else { "OK" };
+			let status = if s.has_errored {
+				"ERRORED"
+			} else if s.hung {
+				"HUNG"
+			} else {
+				"OK"
+			};
 			if !s.has_errored {
 				debug!(
 					LOGGER,
 					"Mining: Plugin {} - Device {} ({}) at Cucka{}{} - Status: {} : Last Graph time: {}s; \
-					 Graphs per second: {:.*} - Total Attempts: {}",
+					 Graphs per second: {:.*} - Total Attempts: {} - Invalid proofs: {}",
 					i,
 					s.device_id,
 					s.get_device_name(),
@@ -149,7 +297,8 @@ impl Controller {
 					last_solution_time_secs,
 					3,
 					last_hashes_per_sec,
-					s.iterations
+					s.iterations,
+					s.invalid_proofs,
 				);
 				if last_hashes_per_sec.is_finite() {
 					sps_total += last_hashes_per_sec;
@@ -157,11 +306,12 @@ impl Controller {
 			} else {
 				debug!(
 					LOGGER,
-					"Mining: Plugin {} - Device {} ({}) Has ERRORED! Reason: {}",
+					"Mining: Plugin {} - Device {} ({}) Has ERRORED! Reason: {} - Restarts: {}",
 					i,
 					s.device_id,
 					s.get_device_name(),
 					s.get_error_reason(),
+					s.restarts,
 				);
 			}
 			i += 1;
@@ -176,7 +326,65 @@ impl Controller {
 			s_stats.mining_stats.add_combined_gps(sps_total);
 			s_stats.mining_stats.target_difficulty = self.current_target_diff;
 			s_stats.mining_stats.block_height = self.current_height;
+			s_stats.mining_stats.pre_pow = self.current_pre_pow.clone();
 			s_stats.mining_stats.device_stats = stats;
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn minute(hours: u32, minutes: u32) -> u32 {
+		hours * 60 + minutes
+	}
+
+	#[test]
+	fn schedule_runs_past_midnight() {
+		let s = Schedule::parse(0, "22:00-07:00").unwrap();
+		assert!(!s.contains(minute(21, 59)));
+		assert!(s.contains(minute(22, 0)));
+		assert!(s.contains(minute(23, 59)));
+		assert!(s.contains(minute(0, 0)));
+		assert!(s.contains(minute(6, 59)));
+		assert!(!s.contains(minute(7, 0)));
+		assert!(!s.contains(minute(12, 0)));
+	}
+
+	#[test]
+	fn schedule_with_several_windows() {
+		let s = Schedule::parse(3, "01:00-02:30, 12:00-13:00,23:30-00:15").unwrap();
+		assert_eq!(s.id, 3);
+		assert_eq!(s.windows.len(), 3);
+		assert!(!s.contains(minute(0, 59)));
+		assert!(s.contains(minute(1, 0)));
+		assert!(s.contains(minute(2, 29)));
+		assert!(!s.contains(minute(2, 30)));
+		assert!(!s.contains(minute(11, 59)));
+		assert!(s.contains(minute(12, 0)));
+		assert!(s.contains(minute(12, 59)));
+		assert!(!s.contains(minute(13, 0)));
+		assert!(!s.contains(minute(23, 29)));
+		assert!(s.contains(minute(23, 30)));
+		assert!(s.contains(minute(0, 14)));
+		assert!(!s.contains(minute(0, 15)));
+	}
+
+	#[test]
+	fn invalid_schedules() {
+		for schedule in [
+			"",
+			"25:00-07:00",
+			"22:00-24:00",
+			"22:60-07:00",
+			"22:00",
+			"22:00 07:00",
+			"22-07",
+			"08:00-08:00",
+			"01:00-02:00,",
+		] {
+			assert!(Schedule::parse(0, schedule).is_err(), "{}", schedule);
+		}
+	}
+}
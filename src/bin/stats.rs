@@ -15,9 +15,12 @@
 //! Miner stats collection types, to be used by tests, logging or GUI/TUI
 //! to collect information about mining status
 
+use crate::types::RejectReason;
 /// Struct to return relevant information about the mining process
 /// back to interested callers (such as the TUI)
 use plugin;
+use std::collections::VecDeque;
+use std::fmt;
 
 #[derive(Clone)]
 pub struct SolutionStats {
@@ -29,8 +32,14 @@ pub struct SolutionStats {
 	pub num_rejected: u32,
 	/// total solutions staled
 	pub num_staled: u32,
+	/// solutions for replaced jobs we never submitted
+	pub num_discarded_stale: u32,
 	/// total blocks found
 	pub num_blocks_found: u32,
+	/// shares found while disconnected and submitted after reconnecting
+	pub num_offline_recovered: u32,
+	/// shares found while disconnected that were never submitted
+	pub num_offline_dropped: u32,
 }
 
 impl Default for SolutionStats {
@@ -40,7 +49,10 @@ impl Default for SolutionStats {
 			num_shares_accepted: 0,
 			num_rejected: 0,
 			num_staled: 0,
+			num_discarded_stale: 0,
 			num_blocks_found: 0,
+			num_offline_recovered: 0,
+			num_offline_dropped: 0,
 		}
 	}
 }
@@ -53,6 +65,8 @@ pub struct MiningStats {
 	pub block_height: u64,
 	/// current target for share difficulty we're working on
 	pub target_difficulty: u64,
+	/// decoded header of the job we're mining
+	pub pre_pow: Option<cuckoo::PrePow>,
 	/// solution statistics
 	pub solution_stats: SolutionStats,
 	/// Individual device status from Cuckoo-Miner
@@ -65,6 +79,7 @@ impl Default for MiningStats {
 			combined_gps: vec![],
 			block_height: 0,
 			target_difficulty: 0,
+			pre_pow: None,
 			solution_stats: SolutionStats::default(),
 			device_stats: vec![],
 		}
@@ -87,6 +102,87 @@ impl MiningStats {
 	}
 }
 
+/// Number of round-trip times kept per kind of request
+const LATENCY_WINDOW: usize = 500;
+
+/// Rolling window of round-trip times for one kind of request, in
+/// milliseconds
+#[derive(Clone, Default)]
+pub struct LatencyStats {
+	samples: VecDeque<f64>,
+}
+
+impl LatencyStats {
+	pub fn add(&mut self, ms: f64) {
+		self.samples.push_back(ms);
+		if self.samples.len() > LATENCY_WINDOW {
+			self.samples.pop_front();
+		}
+	}
+
+	/// Latency below which `pct` percent of the recorded requests fall
+	pub fn percentile(&self, pct: f64) -> Option<f64> {
+		if self.samples.is_empty() {
+			return None;
+		}
+		let mut sorted: Vec<f64> = self.samples.iter().copied().collect();
+		sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+		let rank = (pct / 100.0 * sorted.len() as f64).ceil() as usize;
+		Some(sorted[rank.max(1).min(sorted.len()) - 1])
+	}
+
+	pub fn count(&self) -> usize {
+		self.samples.len()
+	}
+}
+
+impl fmt::Display for LatencyStats {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match (
+			self.percentile(50.0),
+			self.percentile(90.0),
+			self.percentile(99.0),
+		) {
+			(Some(p50), Some(p90), Some(p99)) => write!(
+				f,
+				"p50 {:.0}ms, p90 {:.0}ms, p99 {:.0}ms ({} samples)",
+				p50,
+				p90,
+				p99,
+				self.count()
+			),
+			_ => write!(f, "no samples"),
+		}
+	}
+}
+
+/// Shares rejected by the server, by the reason it gave
+#[derive(Clone, Default)]
+pub struct RejectStats {
+	/// submitted for a job the server had moved on from
+	pub too_late: u32,
+	/// below the share difficulty
+	pub low_difficulty: u32,
+	/// solution didn't validate
+	pub invalid: u32,
+	/// server isn't ready to take shares (syncing or not logged in)
+	pub not_ready: u32,
+	/// any other error code
+	pub other: u32,
+}
+
+impl RejectStats {
+	pub fn add(&mut self, reason: RejectReason) {
+		match reason {
+			RejectReason::TooLate => self.too_late += 1,
+			RejectReason::LowDifficulty => self.low_difficulty += 1,
+			RejectReason::Invalid => self.invalid += 1,
+			RejectReason::NotReady => self.not_ready += 1,
+			RejectReason::Other => self.other += 1,
+		}
+	}
+}
+
 #[derive(Clone)]
 pub struct ClientStats {
 	/// Server we're connected to
@@ -99,6 +195,20 @@ pub struct ClientStats {
 	pub last_message_sent: String,
 	/// Last response/command received from server
 	pub last_message_received: String,
+	/// Requests the server never answered
+	pub requests_timed_out: u32,
+	/// Jobs from the server we refused to hand to the solvers
+	pub jobs_rejected: u32,
+	/// Round-trip times of share submissions
+	pub submit_latency: LatencyStats,
+	/// Round-trip times of logins
+	pub login_latency: LatencyStats,
+	/// Round-trip times of job template requests
+	pub getjobtemplate_latency: LatencyStats,
+	/// Round-trip times of status requests
+	pub status_latency: LatencyStats,
+	/// Rejected shares by reason
+	pub rejects: RejectStats,
 }
 
 impl Default for ClientStats {
@@ -109,6 +219,13 @@ impl Default for ClientStats {
 			connection_status: "Connection Status: Starting".to_string(),
 			last_message_sent: "Last Message Sent: None".to_string(),
 			last_message_received: "Last Message Received: None".to_string(),
+			requests_timed_out: 0,
+			jobs_rejected: 0,
+			submit_latency: LatencyStats::default(),
+			login_latency: LatencyStats::default(),
+			getjobtemplate_latency: LatencyStats::default(),
+			status_latency: LatencyStats::default(),
+			rejects: RejectStats::default(),
 		}
 	}
 }
@@ -129,3 +246,38 @@ impl Default for Stats {
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn no_latency_percentiles_without_samples() {
+		let latency = LatencyStats::default();
+		assert_eq!(latency.percentile(50.0), None);
+		assert_eq!(latency.to_string(), "no samples");
+	}
+
+	#[test]
+	fn single_latency_sample_is_every_percentile() {
+		let mut latency = LatencyStats::default();
+		latency.add(42.0);
+		for pct in [0.0, 1.0, 50.0, 99.0, 100.0] {
+			assert_eq!(latency.percentile(pct), Some(42.0));
+		}
+		assert_eq!(latency.count(), 1);
+	}
+
+	#[test]
+	fn latency_window_keeps_the_latest_samples() {
+		let mut latency = LatencyStats::default();
+		for ms in 1..=LATENCY_WINDOW + 100 {
+			latency.add(ms as f64);
+		}
+		assert_eq!(latency.count(), LATENCY_WINDOW);
+		assert_eq!(latency.percentile(0.0), Some(101.0));
+		assert_eq!(latency.percentile(50.0), Some(350.0));
+		assert_eq!(latency.percentile(99.0), Some(595.0));
+		assert_eq!(latency.percentile(100.0), Some(600.0));
+	}
+}
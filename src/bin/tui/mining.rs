@@ -37,6 +37,8 @@ enum MiningDeviceColumn {
 	DeviceName,
 	EdgeBits,
 	ErrorStatus,
+	Restarts,
+	InvalidProofs,
 	LastGraphTime,
 	GraphsPerSecond,
 }
@@ -49,6 +51,8 @@ impl MiningDeviceColumn {
 			MiningDeviceColumn::DeviceName => "Name",
 			MiningDeviceColumn::EdgeBits => "Graph Size",
 			MiningDeviceColumn::ErrorStatus => "Status",
+			MiningDeviceColumn::Restarts => "Restarts",
+			MiningDeviceColumn::InvalidProofs => "Invalid",
 			MiningDeviceColumn::LastGraphTime => "Last Graph Time",
 			MiningDeviceColumn::GraphsPerSecond => "GPS",
 		}
@@ -66,10 +70,14 @@ impl TableViewItem<MiningDeviceColumn> for SolverStats {
 			MiningDeviceColumn::ErrorStatus => {
 				if self.has_errored {
 					String::from("Errored")
+				} else if self.hung {
+					String::from("Hung")
 				} else {
 					String::from("OK")
 				}
 			}
+			MiningDeviceColumn::Restarts => format!("{}", self.restarts),
+			MiningDeviceColumn::InvalidProofs => format!("{}", self.invalid_proofs),
 			MiningDeviceColumn::LastGraphTime => format!("{}s", last_solution_time_secs),
 			MiningDeviceColumn::GraphsPerSecond => {
 				format!("{:.*}", 4, 1.0 / last_solution_time_secs)
@@ -90,7 +98,11 @@ impl TableViewItem<MiningDeviceColumn> for SolverStats {
 			MiningDeviceColumn::DeviceId => self.device_id.cmp(&other.device_id),
 			MiningDeviceColumn::DeviceName => self.device_name.cmp(&other.device_name),
 			MiningDeviceColumn::EdgeBits => self.edge_bits.cmp(&other.edge_bits),
-			MiningDeviceColumn::ErrorStatus => self.has_errored.cmp(&other.has_errored),
+			MiningDeviceColumn::ErrorStatus => {
+				(self.has_errored, self.hung).cmp(&(other.has_errored, other.hung))
+			}
+			MiningDeviceColumn::Restarts => self.restarts.cmp(&other.restarts),
+			MiningDeviceColumn::InvalidProofs => self.invalid_proofs.cmp(&other.invalid_proofs),
 			MiningDeviceColumn::LastGraphTime => {
 				self.last_solution_time.cmp(&other.last_solution_time)
 			}
@@ -107,7 +119,7 @@ impl TUIStatusListener for TUIMiningView {
 	fn create() -> Box<dyn View> {
 		let table_view = TableView::<SolverStats, MiningDeviceColumn>::new()
 			.column(MiningDeviceColumn::Plugin, "Plugin", |c| {
-				c.width_percent(20)
+				c.width_percent(15)
 			})
 			.column(MiningDeviceColumn::DeviceId, "Device ID", |c| {
 				c.width_percent(5)
@@ -119,6 +131,12 @@ impl TUIStatusListener for TUIMiningView {
 			.column(MiningDeviceColumn::ErrorStatus, "Status", |c| {
 				c.width_percent(8)
 			})
+			.column(MiningDeviceColumn::Restarts, "Restarts", |c| {
+				c.width_percent(7)
+			})
+			.column(MiningDeviceColumn::InvalidProofs, "Invalid", |c| {
+				c.width_percent(5)
+			})
 			.column(MiningDeviceColumn::LastGraphTime, "Graph Time", |c| {
 				c.width_percent(10)
 			})
@@ -138,6 +156,10 @@ impl TUIStatusListener for TUIMiningView {
 				LinearLayout::new(Orientation::Horizontal)
 					.child(TextView::new("  ").with_name("network_info")),
 			)
+			.child(
+				LinearLayout::new(Orientation::Horizontal)
+					.child(TextView::new("  ").with_name("header_info")),
+			)
 			.child(
 				LinearLayout::new(Orientation::Horizontal)
 					.child(TextView::new("  ").with_name("mining_statistics")),
@@ -215,6 +237,17 @@ impl TUIStatusListener for TUIMiningView {
 		c.call_on_name("network_info", |t: &mut TextView| {
 			t.set_content(basic_network_info);
 		});
+		let header_info = match mining_stats.pre_pow.as_ref() {
+			Some(p) if client_stats.connected => format!(
+				"Block Timestamp: {} - Previous Block: {}",
+				time::at_utc(time::Timespec::new(p.timestamp, 0)).rfc3339(),
+				util::to_hex(p.prev_hash.to_vec())
+			),
+			_ => "  ".to_string(),
+		};
+		c.call_on_name("header_info", |t: &mut TextView| {
+			t.set_content(header_info);
+		});
 
 		c.call_on_name("last_message_sent", |t: &mut TextView| {
 			t.set_content(client_stats.last_message_sent.clone());
@@ -225,11 +258,12 @@ impl TUIStatusListener for TUIMiningView {
 
 		if mining_stats.solution_stats.num_solutions_found > 0 {
 			let sol_stat = format!(
-				"Solutions found: {}. Accepted: {}, Rejected: {}, Stale: {}, Blocks found: {}",
+				"Solutions found: {}. Accepted: {}, Rejected: {}, Stale: {}, Discarded stale: {}, Blocks found: {}",
 				mining_stats.solution_stats.num_solutions_found,
 				mining_stats.solution_stats.num_shares_accepted,
 				mining_stats.solution_stats.num_rejected,
 				mining_stats.solution_stats.num_staled,
+				mining_stats.solution_stats.num_discarded_stale,
 				mining_stats.solution_stats.num_blocks_found,
 			);
 			c.call_on_name("mining_statistics", |t: &mut TextView| {
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
 use serde_json::Value;
 
 /// Types used for stratum
@@ -26,7 +28,9 @@ pub struct JobTemplate {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RpcRequest {
+	#[serde(default, deserialize_with = "lenient_id")]
 	pub id: String,
+	#[serde(default)]
 	pub jsonrpc: String,
 	pub method: String,
 	pub params: Option<Value>,
@@ -34,19 +38,93 @@ pub struct RpcRequest {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RpcResponse {
+	#[serde(default, deserialize_with = "lenient_id")]
 	pub id: String,
-	pub method: String,
+	/// not every server echoes the method of the request back
+	pub method: Option<String>,
 	pub jsonrpc: String,
 	pub result: Option<Value>,
 	pub error: Option<RpcError>,
 }
 
+/// Server notifications often carry a null id, and some servers use
+/// numeric ids
+fn lenient_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+	Ok(match Value::deserialize(deserializer)? {
+		Value::String(s) => s,
+		Value::Null => String::new(),
+		v => v.to_string(),
+	})
+}
+
+/// Ports may be sent as numbers or as strings
+fn lenient_port<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u16>, D::Error> {
+	match Value::deserialize(deserializer)? {
+		Value::Null => Ok(None),
+		Value::Number(n) => n
+			.as_u64()
+			.and_then(|p| u16::try_from(p).ok())
+			.map(Some)
+			.ok_or_else(|| D::Error::custom(format!("invalid port {}", n))),
+		Value::String(s) => s
+			.trim()
+			.parse()
+			.map(Some)
+			.map_err(|_| D::Error::custom(format!("invalid port {}", s))),
+		v => Err(D::Error::custom(format!("invalid port {}", v))),
+	}
+}
+
+/// Some servers send fractional difficulties, which we round up so the
+/// shares we send are never below what was asked for
+fn lenient_difficulty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+	let v = Value::deserialize(deserializer)?;
+	match (v.as_u64(), v.as_f64()) {
+		(Some(d), _) => Ok(d),
+		(None, Some(d)) if d >= 0.0 && d <= u64::MAX as f64 => Ok(d.ceil() as u64),
+		_ => Err(D::Error::custom(format!("invalid difficulty {}", v))),
+	}
+}
+
+/// Params of server-pushed messages may be given by position rather than
+/// by name, in which case line them up with the given field names
+fn named_params(params: Value, fields: &[&str]) -> Value {
+	match params {
+		Value::Array(values) => {
+			Value::Object(fields.iter().map(|f| f.to_string()).zip(values).collect())
+		}
+		v => v,
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RpcError {
 	pub code: i32,
 	pub message: String,
 }
 
+/// Why the server turned down a share, going by the error code it sent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RejectReason {
+	TooLate,
+	LowDifficulty,
+	Invalid,
+	NotReady,
+	Other,
+}
+
+impl RpcError {
+	pub fn reject_reason(&self) -> RejectReason {
+		match self.code {
+			-32503 => RejectReason::TooLate,
+			-32501 => RejectReason::LowDifficulty,
+			-32502 => RejectReason::Invalid,
+			-32000 | -32500 | -32701 => RejectReason::NotReady,
+			_ => RejectReason::Other,
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LoginParams {
 	pub login: String,
@@ -54,7 +132,7 @@ pub struct LoginParams {
 	pub agent: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SubmitParams {
 	pub height: u64,
 	pub job_id: u64,
@@ -63,6 +141,49 @@ pub struct SubmitParams {
 	pub pow: Vec<u64>,
 }
 
+/// New share difficulty pushed by the server, which applies to the
+/// current job and all later ones
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SetDifficultyParams {
+	#[serde(deserialize_with = "lenient_difficulty")]
+	pub difficulty: u64,
+}
+
+impl SetDifficultyParams {
+	pub fn from_params(params: Value) -> Result<SetDifficultyParams, serde_json::Error> {
+		serde_json::from_value(named_params(params, &["difficulty"]))
+	}
+}
+
+/// Hex-encoded bytes the server wants all our nonces to start with
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SetExtranonceParams {
+	pub extranonce: String,
+}
+
+impl SetExtranonceParams {
+	pub fn from_params(params: Value) -> Result<SetExtranonceParams, serde_json::Error> {
+		serde_json::from_value(named_params(params, &["extranonce"]))
+	}
+}
+
+/// Request from the server to reconnect, optionally elsewhere. A missing
+/// host or port means the current one.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReconnectParams {
+	pub host: Option<String>,
+	#[serde(default, deserialize_with = "lenient_port")]
+	pub port: Option<u16>,
+	/// seconds to wait before reconnecting
+	pub wait: Option<u64>,
+}
+
+impl ReconnectParams {
+	pub fn from_params(params: Value) -> Result<ReconnectParams, serde_json::Error> {
+		serde_json::from_value(named_params(params, &["host", "port", "wait"]))
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WorkerStatus {
 	pub id: String,
@@ -78,6 +199,10 @@ pub struct WorkerStatus {
 pub enum MinerMessage {
 	// Height, difficulty, pre_pow
 	ReceivedJob(u64, u64, u64, String),
+	// difficulty
+	SetDifficulty(u64),
+	// hex extranonce, empty to clear
+	SetExtranonce(String),
 	StopJob,
 	Shutdown,
 }
@@ -86,5 +211,41 @@ pub enum MinerMessage {
 pub enum ClientMessage {
 	// height, job_id, edge_bits, nonce, pow
 	FoundSolution(u64, u64, u32, u64, Vec<u64>),
+	// connection id, line received from the server
+	ServerMessage(u64, String),
+	// connection id
+	ServerDisconnected(u64),
 	Shutdown,
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn reconnect_port_may_be_a_string() {
+		let port = |params| ReconnectParams::from_params(params).map(|p| p.port);
+		assert_eq!(port(json!({"port": 3416})).unwrap(), Some(3416));
+		assert_eq!(port(json!({"port": "3416"})).unwrap(), Some(3416));
+		assert_eq!(
+			port(json!(["pool.example", "3416", 5])).unwrap(),
+			Some(3416)
+		);
+		assert_eq!(port(json!({"port": null})).unwrap(), None);
+		assert_eq!(port(json!({})).unwrap(), None);
+		assert!(port(json!({"port": "http"})).is_err());
+		assert!(port(json!({"port": 70000})).is_err());
+	}
+
+	#[test]
+	fn fractional_difficulty_is_rounded_up() {
+		let difficulty = |params| SetDifficultyParams::from_params(params).map(|p| p.difficulty);
+		assert_eq!(difficulty(json!([16])).unwrap(), 16);
+		assert_eq!(difficulty(json!({"difficulty": 16.0})).unwrap(), 16);
+		assert_eq!(difficulty(json!({"difficulty": 15.2})).unwrap(), 16);
+		assert_eq!(difficulty(json!([0.5])).unwrap(), 1);
+		assert!(difficulty(json!([-1.5])).is_err());
+		assert!(difficulty(json!(["high"])).is_err());
+	}
+}